@@ -0,0 +1,250 @@
+use std::path::PathBuf;
+
+use crate::constants;
+use crate::game::camera::ProjectionMode;
+
+/// Which pipeline fills the G-buffer. Both write the same contents, so
+/// everything after it doesn't care.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RasterBackend {
+    /// binning and rasterizing in compute shaders, see `binning.wgsl`
+    Compute,
+    /// a regular render pipeline with a depth attachment, see
+    /// `hardware.wgsl`
+    Hardware,
+}
+
+/// How the internal resolution is stretched over the window.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scaling {
+    /// as big as fits, keeping the aspect ratio
+    Fit,
+    /// the biggest whole-number multiple that fits, so every pixel comes
+    /// out the same size
+    Integer,
+}
+
+/// Settings picked at startup.
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub raster: RasterBackend,
+    /// internal resolution, in pixels
+    pub resolution: (u32, u32),
+    pub scaling: Scaling,
+    /// the camera's to start with, P steps through the rest
+    pub projection: ProjectionMode,
+    /// whether the minimap starts out drawn, M toggles it
+    pub minimap: bool,
+    /// render one frame without a window, save it here and quit
+    pub screenshot: Option<PathBuf>,
+    /// the file K adds the camera's keyframes to and C plays them from
+    pub keyframes: Option<PathBuf>,
+    /// play the keyframes without a window, save each frame in this
+    /// directory and quit
+    pub export: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            raster: RasterBackend::Compute,
+            resolution: constants::SCREEN_PIXELS,
+            scaling: Scaling::Fit,
+            projection: ProjectionMode::Isometric,
+            minimap: true,
+            screenshot: None,
+            keyframes: None,
+            export: None,
+        }
+    }
+}
+
+impl Config {
+    /// Reads the settings from command line arguments (without the program
+    /// name), e.g. `--raster hardware`, `--resolution 320x180`,
+    /// `--scale integer`, `--projection klein`, `--minimap off`,
+    /// `--screenshot frame.png`, `--keyframes path.txt` or `--export frames`.
+    pub fn from_args(args: impl IntoIterator<Item = String>)
+        -> Result<Config, String>
+    {
+        let mut out = Config::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--raster" => {
+                    out.raster = match args.next().as_deref() {
+                        Some("compute") => RasterBackend::Compute,
+                        Some("hardware") => RasterBackend::Hardware,
+                        other => return Err(format!(
+                            "--raster wants compute or hardware, got {:?}",
+                            other
+                        )),
+                    };
+                }
+                "--resolution" => {
+                    out.resolution = match args.next().as_deref()
+                        .and_then(parse_resolution)
+                    {
+                        Some(resolution) => resolution,
+                        None => return Err(format!(
+                            "--resolution wants a size like 640x360, up to \
+                                {} either way",
+                            constants::MAX_RESOLUTION,
+                        )),
+                    };
+                }
+                "--scale" => {
+                    out.scaling = match args.next().as_deref() {
+                        Some("fit") => Scaling::Fit,
+                        Some("integer") => Scaling::Integer,
+                        other => return Err(format!(
+                            "--scale wants fit or integer, got {:?}",
+                            other
+                        )),
+                    };
+                }
+                "--projection" => {
+                    out.projection = match args.next().as_deref() {
+                        Some("isometric") => ProjectionMode::Isometric,
+                        Some("poincare") => ProjectionMode::PoincareDisk,
+                        Some("klein") => ProjectionMode::Klein,
+                        Some("half-plane") => ProjectionMode::UpperHalfPlane,
+                        Some("gnomonic") => ProjectionMode::Gnomonic,
+                        Some("first-person") => ProjectionMode::FirstPerson,
+                        other => return Err(format!(
+                            "--projection wants isometric, poincare, klein, \
+                                half-plane, gnomonic or first-person, \
+                                got {:?}",
+                            other
+                        )),
+                    };
+                }
+                "--minimap" => {
+                    out.minimap = match args.next().as_deref() {
+                        Some("on") => true,
+                        Some("off") => false,
+                        other => return Err(format!(
+                            "--minimap wants on or off, got {:?}",
+                            other
+                        )),
+                    };
+                }
+                "--screenshot" => match args.next() {
+                    Some(path) => out.screenshot = Some(path.into()),
+                    None => return Err(
+                        "--screenshot wants a file to save to".to_owned()
+                    ),
+                },
+                "--keyframes" => match args.next() {
+                    Some(path) => out.keyframes = Some(path.into()),
+                    None => return Err(
+                        "--keyframes wants a file to keep them in".to_owned()
+                    ),
+                },
+                "--export" => match args.next() {
+                    Some(dir) => out.export = Some(dir.into()),
+                    None => return Err(
+                        "--export wants a directory to save to".to_owned()
+                    ),
+                },
+                _ => return Err(format!("unknown argument {:?}", arg)),
+            }
+        }
+        if out.export.is_some() && out.keyframes.is_none() {
+            return Err("--export wants --keyframes to play".to_owned());
+        }
+        Ok(out)
+    }
+}
+
+/// `WIDTHxHEIGHT`, both nonzero.
+fn parse_resolution(arg: &str) -> Option<(u32, u32)> {
+    let (width, height) = arg.split_once('x')?;
+    let size = (width.parse().ok()?, height.parse().ok()?);
+    let fits = |side| (1..=constants::MAX_RESOLUTION).contains(&side);
+    (fits(size.0) && fits(size.1)).then_some(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Config, String> {
+        Config::from_args(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn defaults_to_compute() {
+        assert_eq!(parse(&[]).unwrap().raster, RasterBackend::Compute);
+    }
+
+    #[test]
+    fn picks_raster_backend() {
+        assert_eq!(
+            parse(&["--raster", "hardware"]).unwrap().raster,
+            RasterBackend::Hardware
+        );
+        assert!(parse(&["--raster", "software"]).is_err());
+        assert!(parse(&["--raster"]).is_err());
+        assert!(parse(&["--fast"]).is_err());
+    }
+
+    #[test]
+    fn picks_resolution_and_scaling() {
+        let config = parse(&[]).unwrap();
+        assert_eq!(config.resolution, constants::SCREEN_PIXELS);
+        assert_eq!(config.scaling, Scaling::Fit);
+
+        let config =
+            parse(&["--resolution", "320x180", "--scale", "integer"])
+                .unwrap();
+        assert_eq!(config.resolution, (320, 180));
+        assert_eq!(config.scaling, Scaling::Integer);
+
+        assert!(parse(&["--resolution", "320"]).is_err());
+        assert!(parse(&["--resolution", "0x180"]).is_err());
+        // past what the rasterizer's fixed-point math covers
+        assert!(parse(&["--resolution", "3840x2160"]).is_err());
+        let most = format!("{0}x{0}", constants::MAX_RESOLUTION);
+        assert!(parse(&["--resolution", &most]).is_ok());
+        assert!(parse(&["--scale", "stretch"]).is_err());
+    }
+
+    #[test]
+    fn picks_projection() {
+        assert_eq!(parse(&[]).unwrap().projection, ProjectionMode::Isometric);
+        assert_eq!(
+            parse(&["--projection", "half-plane"]).unwrap().projection,
+            ProjectionMode::UpperHalfPlane
+        );
+        assert!(parse(&["--projection", "mercator"]).is_err());
+    }
+
+    #[test]
+    fn picks_minimap() {
+        assert!(parse(&[]).unwrap().minimap);
+        assert!(!parse(&["--minimap", "off"]).unwrap().minimap);
+        assert!(parse(&["--minimap", "hidden"]).is_err());
+    }
+
+    #[test]
+    fn takes_a_screenshot_path() {
+        assert_eq!(parse(&[]).unwrap().screenshot, None);
+        assert_eq!(
+            parse(&["--screenshot", "out.png"]).unwrap().screenshot,
+            Some(PathBuf::from("out.png"))
+        );
+        assert!(parse(&["--screenshot"]).is_err());
+    }
+
+    #[test]
+    fn exports_keyframes() {
+        let config =
+            parse(&["--export", "frames", "--keyframes", "path.txt"])
+                .unwrap();
+        assert_eq!(config.keyframes, Some(PathBuf::from("path.txt")));
+        assert_eq!(config.export, Some(PathBuf::from("frames")));
+        assert!(parse(&["--export", "frames"]).is_err());
+        assert!(parse(&["--keyframes"]).is_err());
+    }
+}
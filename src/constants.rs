@@ -1,12 +1,53 @@
+// default internal resolution, see `--resolution`
 pub const SCREEN_PIXELS: (u32, u32) = (640, 360);
-pub const G_BUFFER_NUMS: u32 = 3;
-pub const WORLD_SCALE: f32 = 4.5;
+// internal resolutions R steps through while playing
+pub const RESOLUTIONS: [(u32, u32); 3] = [(320, 180), (640, 360), (960, 540)];
+pub const WORLD_SCALE: f32 = 4.5; // default map scale
+pub const MAX_MAP_SCALE: f32 = 20.0; // furthest the map zooms in
+// tiles closer than this to the camera stream in their neighbours, see
+// `Game::update`
+pub const STREAM_RADIUS: f64 = 1.3;
 pub const NEAR_PLANE: f32 = 2500.0;
 pub const FAR_PLANE: f32 = -2500.0;
+pub const MAX_TILES: u32 = 256; // drawn per frame
+pub const HULL_STEPS: u32 = 8; // points along each side culling checks
+pub const MINIMAP_SIZE: f32 = 0.3; // of the image's height, across
+pub const MINIMAP_VERTICES: u32 = 1 << 14; // drawn per frame
+// the camera following the player, see `Camera::follow`
+pub const FOLLOW_EASING: f64 = 0.15; // of the way there each update
+pub const LOOK_AHEAD: f64 = 0.03; // how far ahead of the player it looks
+// cinematics, see `cinematic::Playback`
+pub const KEYFRAME_FRAMES: u32 = 90; // updates from one keyframe to the next
+// furthest apart the tiles of two keyframes in a row can be
+pub const KEYFRAME_REACH: f64 = 4.0;
+// first-person camera, see `ProjectionMode::FirstPerson`
+pub const EYE_HEIGHT: f32 = 0.04;
+pub const FIELD_OF_VIEW: f32 = 1.5707964; // radians across the screen
+pub const NEAR_DISTANCE: f32 = 0.005; // nothing closer to the eye is drawn
+pub const LAMP_DISTANCE: f64 = 0.3; // how far ahead the camera's light is
+pub const MAX_PITCH: f32 = 1.4; // radians up or down
+// hyperbolic distances where tiles drop to their next mesh, see
+// `mapdata::lod_for_distance`
+pub const LOD_DISTANCES: [f64; 2] = [1.6, 2.2];
+// how far past one of them a tile has to get before it switches
+pub const LOD_HYSTERESIS: f64 = 0.1;
 
-pub const G_BUFFER_SIZE: u32 = 
-      SCREEN_PIXELS.0
-    * SCREEN_PIXELS.1
-    * 4 // size of u32
-    * G_BUFFER_NUMS; // data points per pixel
-    //+ 8; // two extra numbers for depth info??
+// screen-space shadows, see `shadow()` in lighting.wgsl
+pub const SHADOW_STEPS: u32 = 24;
+pub const SHADOW_BIAS: f32 = 4.0;
+pub const SHADOW_THICKNESS: f32 = 200.0;
+
+// fixed-point rasterizing, see binning.wgsl and g_buffer.wgsl
+pub const SUBPIXEL_BITS: u32 = 4;
+// pixels, the most the i32 edge functions stay exact for
+pub const MAX_TRIANGLE_EXTENT: u32 = 2047;
+pub const GUARD_BAND: u32 = 64; // pixels clipped triangles may overhang
+// pixels either way, so triangles clipped to the guard band still fit in
+// MAX_TRIANGLE_EXTENT with a subpixel of rounding to spare
+pub const MAX_RESOLUTION: u32 = MAX_TRIANGLE_EXTENT - 2 * GUARD_BAND - 1;
+pub const BIN_SIZE: u32 = 16; // pixels, matches `raster_main`'s workgroup
+// triangles per bin, twice the most seen: 969, at 320x180 with the Klein
+// disk zoomed all the way out over every tile out to 3.8 at full detail
+pub const BIN_CAPACITY: u32 = 2048;
+// per frame, three times the most seen: 42936, in the same sort of views
+pub const MAX_SCREEN_TRIANGLES: u32 = 1 << 17;
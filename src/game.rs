@@ -1,19 +1,36 @@
+use std::f64::consts::FRAC_PI_2;
+use std::path::{ Path, PathBuf };
+
 use winit::{ event::*, window::Window };
+use crate::config::{ Config, Scaling };
+use crate::constants;
 use self::screen::Screen;
-use self::camera::Camera;
-use self::tile::Tile;
+use self::camera::{ Camera, ProjectionMode };
+use self::cinematic::{ Keyframe, Playback };
+use self::player::Player;
+use self::tile::{ mapdata, Tile };
 use self::tile::permutation::GroupElt;
 
 mod screen;
-mod camera;
+pub mod camera;
+mod cinematic;
+mod isometry;
+mod material;
+mod player;
 mod tile;
 
 pub struct Game{
     screen: Screen,
     camera: Camera,
+    player: Player,
     tiles: Vec<Tile>,
     codes: Vec<u32>,
 
+    // recorded with K, or read from here if there's a file
+    keyframes: Vec<Keyframe>,
+    keyframe_file: Option<PathBuf>,
+    playback: Option<Playback>,
+
     l_pressed: bool, // MAKE LIKE A HASH TABLE FOR THIS
     r_pressed: bool,
     u_pressed: bool,
@@ -21,89 +38,100 @@ pub struct Game{
 }
 
 impl Game {
-    pub async fn new(window: &Window) -> Game {
-        let screen = Screen::new(window).await;
-        let camera = Camera::new();
+    pub async fn new(window: &Window, config: &Config)
+        -> Result<Game, String>
+    {
+        Ok(Self::with_screen(Screen::new(window, config).await?, config))
+    }
+
+    /// A game with no window, for taking screenshots.
+    pub async fn headless(config: &Config) -> Result<Game, String> {
+        Ok(Self::with_screen(Screen::headless(config).await?, config))
+    }
+
+    fn with_screen(screen: Screen, config: &Config) -> Game {
+        let player = Player::new();
+        let mut camera = Camera::new();
+        camera.set_projection(config.projection);
+        camera.snap_to(player.frame());
 
         let mut out = Self {
             screen,
             camera,
+            player,
             tiles: Vec::<Tile>::new(),
             codes: Vec::<u32>::new(),
 
+            keyframes: Vec::new(),
+            keyframe_file: config.keyframes.clone(),
+            playback: None,
+
             l_pressed: false,
             r_pressed: false,
             u_pressed: false,
             d_pressed: false,
         };
 
-        out.tiles.push(Tile::new(tile::permutation::IDENTITY));
+        let mut first = Tile::new(tile::permutation::IDENTITY);
+        out.screen.acquire_tile(&mut first);
+        out.tiles.push(first);
         out.codes.push(0_u32);
 
         out
     }
 
     pub fn update(&mut self) {
-        /* #region STUPDI MOVEMENT GARBAGE (FIX) (STUPID) */
-        let distance = 0.005f32;
-
-        let direction = self.camera.get_direction();
-        let position = self.camera.get_position();
-        if self.u_pressed {
-            let new_pos = [
-                position[0] * distance.cosh() + direction[0] * distance.sinh(),
-                position[1] * distance.cosh() + direction[1] * distance.sinh(),
-                position[2],
-                position[3] * distance.cosh() + direction[2] * distance.sinh()];
-            let new_dir = [
-                position[0] * distance.sinh() + direction[0] * distance.cosh(),
-                position[1] * distance.sinh() + direction[1] * distance.cosh(),
-                position[3] * distance.sinh() + direction[2] * distance.cosh()];
-            self.camera.set_camera(new_pos, new_dir);
-        } else if self.d_pressed {
-            let new_pos = [
-                position[0] * distance.cosh() + -direction[0] * distance.sinh(),
-                position[1] * distance.cosh() + -direction[1] * distance.sinh(),
-                position[2],
-                position[3] * distance.cosh() + -direction[2] * distance.sinh()];
-            let new_dir = [
-                -position[0] * distance.sinh() + direction[0] * distance.cosh(),
-                -position[1] * distance.sinh() + direction[1] * distance.cosh(),
-                -position[3] * distance.sinh() + direction[2] * distance.cosh()];
-            self.camera.set_camera(new_pos, new_dir);
+        if let Some(playback) = &mut self.playback {
+            if playback.step(&mut self.camera, &mut self.tiles) {
+                // taking the player along, for re-centering
+                self.player.set_frame(self.camera.frame());
+            } else {
+                self.playback = None;
+            }
+        }
+        if self.playback.is_none() {
+            let forward = self.u_pressed as i8 - self.d_pressed as i8;
+            let left = self.l_pressed as i8 - self.r_pressed as i8;
+            if forward != 0 || left != 0 {
+                self.player.walk(
+                    forward as f64 * 0.005, left as f64 * 0.005);
+            }
+            self.camera.follow(self.player.frame());
         }
 
-        let position = self.camera.get_position();
-        let old_direction = self.camera.get_direction();
-        let direction = [
-            position[1] * old_direction[2] - position[3] * old_direction[1],
-            position[3] * old_direction[0] - position[0] * old_direction[2],
-            position[1] * old_direction[0] - position[0] * old_direction[1],
-        ];
-
-        if self.l_pressed {
-            let new_pos = [
-                position[0] * distance.cosh() + direction[0] * distance.sinh(),
-                position[1] * distance.cosh() + direction[1] * distance.sinh(),
-                position[2],
-                position[3] * distance.cosh() + direction[2] * distance.sinh()];
-            self.camera.set_camera(new_pos, *old_direction);
-        } else if self.r_pressed {
-            let new_pos = [
-                position[0] * distance.cosh() + -direction[0] * distance.sinh(),
-                position[1] * distance.cosh() + -direction[1] * distance.sinh(),
-                position[2],
-                position[3] * distance.cosh() + -direction[2] * distance.sinh()];
-            self.camera.set_camera(new_pos, *old_direction);
+        self.stream_tiles();
+
+        /* #region MAP RE-CENTERING */
+
+        // the tile the player's on is kept at the origin, so nothing
+        // loaded is ever far enough out to lose precision
+        let position = self.player.get_position();
+        let current = self.tiles.iter().min_by(|a, b|
+            a.distance_from(&position).total_cmp(&b.distance_from(&position)));
+        if let Some(current) = current {
+            if current.distance_from(&[0.0, 0.0, 0.0, 1.0]) > 0.5 {
+                // everything goes along, so nothing seems to move
+                let back = isometry::inverse(&current.get_mat());
+                for tile in self.tiles.iter_mut() {
+                    tile.move_by(&back);
+                }
+                self.player.move_by(&back);
+                self.camera.set_frame(&(back * self.camera.frame()));
+            }
         }
+
         /* #endregion */
+    }
 
-        /* #region TILE LOADING AND UNLOADING */
+    /// Streams in the neighbours of tiles near the camera, drops the ones
+    /// that have got too far, and switches meshes by distance. Says whether
+    /// any tiles came in.
+    fn stream_tiles(&mut self) -> bool {
         let mut add_tiles = Vec::<Tile>::new();
         let mut remove_codes = Vec::<u32>::new();
         for tile in &mut self.tiles {
             let dist = tile.distance_from(self.camera.get_position());
-            if dist < 1.3 {
+            if dist < constants::STREAM_RADIUS {
                 let (a, b) = tile.update_neighbors(
                     self.camera.get_position(),
                     &self.codes
@@ -114,66 +142,45 @@ impl Game {
             } else if dist > 2.8 {
                 remove_codes.push(tile.get_code());
             }
+
+            // switching meshes means switching slot pools too
+            let lod = mapdata::lod_for_distance(dist, tile.get_lod());
+            if lod != tile.get_lod() {
+                self.screen.release_tile(tile);
+                tile.set_lod(lod);
+                self.screen.acquire_tile(tile);
+            }
+        }
+        let added = !add_tiles.is_empty();
+        let streamed = added || !remove_codes.is_empty();
+        for mut tile in add_tiles {
+            tile.set_lod(mapdata::lod_for_distance(
+                tile.distance_from(self.camera.get_position()),
+                tile.get_lod(),
+            ));
+            self.screen.acquire_tile(&mut tile);
+            self.tiles.push(tile);
         }
-        self.tiles.extend(add_tiles);
         let l = self.codes.len();
         let mut dec = 0;
         for i in 0..l {
             if remove_codes.contains(&self.tiles[i - dec].get_code()) {
-                self.tiles.swap_remove(i - dec);
+                let mut tile = self.tiles.swap_remove(i - dec);
+                self.screen.release_tile(&mut tile);
                 self.codes.swap_remove(i - dec);
                 dec += 1;
             }
         }
-        /* #endregion */
-        
-        /* #region MAP RE-CENTERING */
-
-        let dist = self.camera.get_position()[3].acosh();
-        if dist > 3.7 {
-            let mut moved = Vec::<Tile>::new();
-            let mut to_move = Vec::<GroupElt>::new();
-            let mut min_dist = 4.5;
-            let mut closest_tile = &self.tiles[0];
-
-            for tile in &self.tiles {
-                let new_dist = tile.distance_from(
-                    self.camera.get_position()
-                );
-                if new_dist < min_dist {
-                    min_dist = new_dist;
-                    closest_tile = tile;
-                }
-            }
-
-            let new_camera = closest_tile.move_against(
-                self.camera.get_position(),
-                self.camera.get_direction()
-            );
-            self.camera.set_camera(new_camera.0, new_camera.1);
-
-            println!("{:?}", closest_tile.centered_code().get_id());
-
-            to_move.push(closest_tile.centered_code());
-
-            while to_move.len() > 0 && self.tiles.len() > 0 {
-                let mut next = to_move.remove(to_move.len() - 1);
-
-                for i in 0..self.tiles.len() {
-                    if self.tiles[i].get_code() == next.get_id() {
-                        let mut moved_tile = self.tiles.swap_remove(i);
-                        to_move.extend(moved_tile.move_to(next));
-                        self.screen.updade_tile_pos(&mut moved_tile);
-                        moved.push(moved_tile);
-                        break;
-                    }
-                }
-            }
-
-            self.tiles = moved;
+        if streamed {
+            log::debug!("tile slots: {:?}", self.screen.pool_stats());
         }
+        added
+    }
 
-        /* #endregion */
+    /// Streams tiles in until there are no more to come, so the first
+    /// frame drawn doesn't have them popping in ring by ring.
+    pub fn settle(&mut self) {
+        while self.stream_tiles() {}
     }
 
     pub fn handle_event(&mut self, event: &Event<()>, window: &Window) -> bool {
@@ -181,7 +188,7 @@ impl Game {
             Event::WindowEvent {
                 ref event,
                 window_id,
-            } if *window_id == window.id() => if !self.input(event) {
+            } if *window_id == window.id() && !self.input(event) => {
                 match event {
                     WindowEvent::CloseRequested
                     | WindowEvent::KeyboardInput {
@@ -205,7 +212,7 @@ impl Game {
             },
             _ => {}
         }
-        return false
+        false
     }
 
     fn input(&mut self, event: &WindowEvent) -> bool {
@@ -236,6 +243,94 @@ impl Game {
                         self.r_pressed = is_pressed;
                         true
                     }
+                    VirtualKeyCode::R => {
+                        if is_pressed {
+                            self.next_resolution();
+                        }
+                        true
+                    }
+                    VirtualKeyCode::P => {
+                        if is_pressed {
+                            let next = self.camera.get_projection().next();
+                            self.camera.set_projection(next);
+                            log::info!("projection {:?}", next);
+                        }
+                        true
+                    }
+                    // turning and looking up and down, in first person
+                    VirtualKeyCode::Q | VirtualKeyCode::E
+                    | VirtualKeyCode::PageUp | VirtualKeyCode::PageDown
+                        if self.camera.get_projection()
+                            == ProjectionMode::FirstPerson =>
+                    {
+                        if is_pressed {
+                            match keycode {
+                                VirtualKeyCode::Q => self.player.turn(0.05),
+                                VirtualKeyCode::E => self.player.turn(-0.05),
+                                VirtualKeyCode::PageUp =>
+                                    self.camera.tilt(0.05),
+                                _ => self.camera.tilt(-0.05),
+                            }
+                        }
+                        true
+                    }
+                    // quarter turns everywhere else, which the camera
+                    // swings around after
+                    VirtualKeyCode::Q | VirtualKeyCode::E => {
+                        if is_pressed {
+                            self.player.turn(
+                                if *keycode == VirtualKeyCode::Q { 1.0 }
+                                else { -1.0 } * FRAC_PI_2);
+                        }
+                        true
+                    }
+                    VirtualKeyCode::Equals | VirtualKeyCode::NumpadAdd
+                    | VirtualKeyCode::Minus
+                    | VirtualKeyCode::NumpadSubtract => {
+                        if is_pressed {
+                            let zoom_in = matches!(keycode,
+                                VirtualKeyCode::Equals
+                                | VirtualKeyCode::NumpadAdd);
+                            let (w, h) = self.screen.resolution();
+                            self.camera.zoom(
+                                if zoom_in { 1.25 } else { 0.8 },
+                                w as f32 / h as f32);
+                        }
+                        true
+                    }
+                    VirtualKeyCode::K => {
+                        if is_pressed {
+                            self.record_keyframe();
+                        }
+                        true
+                    }
+                    VirtualKeyCode::C => {
+                        if is_pressed {
+                            if self.playing() {
+                                self.playback = None;
+                            } else if let Err(e) = self.play() {
+                                log::warn!("{}", e);
+                            }
+                        }
+                        true
+                    }
+                    VirtualKeyCode::M => {
+                        if is_pressed {
+                            self.screen.show_minimap(
+                                !self.screen.minimap_shown());
+                        }
+                        true
+                    }
+                    VirtualKeyCode::I => {
+                        if is_pressed {
+                            self.screen.set_scaling(
+                                match self.screen.scaling() {
+                                    Scaling::Fit => Scaling::Integer,
+                                    Scaling::Integer => Scaling::Fit,
+                                });
+                        }
+                        true
+                    }
                     _ => false,
                 }
             },
@@ -243,16 +338,85 @@ impl Game {
         }
     }
 
+    /// Adds where the camera is to the keyframes, and to the end of the
+    /// keyframe file if there is one.
+    fn record_keyframe(&mut self) {
+        let Some(keyframe) =
+            Keyframe::of_camera(&self.camera, &mut self.tiles) else {
+            return;
+        };
+        log::info!("keyframe {}", keyframe);
+        if let Some(path) = &self.keyframe_file {
+            if let Err(e) = cinematic::append(path, &keyframe) {
+                log::warn!("couldn't save to {}: {}", path.display(), e);
+            }
+        }
+        self.keyframes.push(keyframe);
+    }
+
+    /// Starts playing the keyframes from the first, one frame an update.
+    /// With a keyframe file they're read from it again, so edits to it
+    /// show up.
+    pub fn play(&mut self) -> Result<(), String> {
+        if let Some(path) = &self.keyframe_file {
+            self.keyframes = cinematic::load(path)?;
+        }
+        let path = cinematic::Path::new(self.keyframes.clone())?;
+
+        // the map is rebuilt around the first keyframe's tile
+        let start = *path.start();
+        for tile in self.tiles.iter_mut() {
+            self.screen.release_tile(tile);
+        }
+        let mut first = Tile::new(GroupElt::from_id(start.tile));
+        self.screen.acquire_tile(&mut first);
+        self.tiles = vec![first];
+        self.codes = vec![start.tile];
+        self.player.set_frame(start.local);
+        self.camera.set_frame(&start.local);
+        self.settle();
+
+        self.playback = Some(Playback::new(path));
+        Ok(())
+    }
+
+    pub fn playing(&self) -> bool { self.playback.is_some() }
+
+    /// Steps to the next of `constants::RESOLUTIONS`, wrapping around.
+    fn next_resolution(&mut self) {
+        let current = self.screen.resolution();
+        let next = constants::RESOLUTIONS.iter()
+            .position(|r| *r == current)
+            .map_or(0, |i| (i + 1) % constants::RESOLUTIONS.len());
+        self.screen.set_resolution(constants::RESOLUTIONS[next]);
+        log::info!("internal resolution {:?}", constants::RESOLUTIONS[next]);
+    }
+
     pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        let len = self.tiles.len();
-        self.screen.render(
-            &self.camera, 
-            &mut self.tiles[0..len]
-        )
+        let result = self.screen.render(
+            &self.camera, self.player.frame(), &self.tiles);
+        log::trace!("tiles: {:?}", self.screen.cull_stats());
+        log::trace!("bins: {:?}", self.screen.bin_stats());
+        result
+    }
+
+    /// Saves what the camera sees right now as a png.
+    pub fn screenshot(&self, path: &Path) -> Result<(), png::EncodingError> {
+        self.screen.screenshot(
+            &self.camera, self.player.frame(), &self.tiles, path)
     }
 
     pub fn reconfigure(&mut self) {
         self.screen.resize(self.screen.size)
     }
 
+}
+
+impl Drop for Game {
+    /// Gives back every tile's slot, which `Tile` checks for.
+    fn drop(&mut self) {
+        for tile in self.tiles.iter_mut() {
+            self.screen.release_tile(tile);
+        }
+    }
 }
\ No newline at end of file
@@ -1,130 +1,571 @@
+use nalgebra::Matrix4;
+use crate::constants;
+use crate::game::isometry::{ self, Motion };
+
+/// How the camera flattens the plane onto the screen. Every mode goes
+/// through `to_model` and then `camera_to_screen`, and first person then
+/// through `first_person`, on the cpu and in `w_to_c` in the shaders.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProjectionMode {
+    /// the hyperboloid itself, seen at a fixed isometric angle
+    Isometric,
+    /// the whole plane in the unit disk, angles kept true
+    PoincareDisk,
+    /// the whole plane in the unit disk, geodesics kept straight
+    Klein,
+    /// the whole plane above a line, with the camera one unit up
+    UpperHalfPlane,
+    /// the hyperboloid projected from its centre onto the plane touching
+    /// it, seen from straight above at the game's own scale. Klein's disk
+    /// is the same map, framed to show the whole plane.
+    Gnomonic,
+    /// standing in H² × E, looking along the heading and tilted by the
+    /// pitch. Every vertex lands where the geodesic ray through it meets
+    /// the screen: straight along H² geodesics sideways, straight up and
+    /// down.
+    FirstPerson,
+}
+
+impl ProjectionMode {
+    pub const ALL: [ProjectionMode; 6] = [
+        ProjectionMode::Isometric,
+        ProjectionMode::PoincareDisk,
+        ProjectionMode::Klein,
+        ProjectionMode::UpperHalfPlane,
+        ProjectionMode::Gnomonic,
+        ProjectionMode::FirstPerson,
+    ];
+
+    /// The one after this in `ALL`, wrapping around.
+    pub fn next(self) -> ProjectionMode {
+        let i = Self::ALL.iter().position(|m| *m == self).unwrap();
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+
+    /// Where the point `a` on the hyperboloid, in camera coordinates,
+    /// lands in the plane of this mode's model. Follows `to_model` in
+    /// `wgsl_camera`.
+    pub fn to_model(self, a: [f32; 4]) -> [f32; 2] {
+        match self {
+            ProjectionMode::Isometric => [a[0], a[1]],
+            ProjectionMode::PoincareDisk => {
+                [a[0] / (1.0 + a[3]), a[1] / (1.0 + a[3])]
+            }
+            ProjectionMode::Klein | ProjectionMode::Gnomonic => {
+                [a[0] / a[3], a[1] / a[3]]
+            }
+            ProjectionMode::UpperHalfPlane => {
+                // i (1 + z) / (1 - z) of the point z in the disk, moved
+                // down so the camera sits at the middle of the screen
+                let x = a[0] / (1.0 + a[3]);
+                let y = a[1] / (1.0 + a[3]);
+                let d = (1.0 - x) * (1.0 - x) + y * y;
+                [-2.0 * y / d, (1.0 - x * x - y * y) / d - 1.0]
+            }
+            ProjectionMode::FirstPerson => log_origin(a),
+        }
+    }
+}
+
+/// The tangent vector at the camera whose geodesic reaches `a`, as long as
+/// the distance to it. Follows `log_origin` in `wgsl_camera`.
+pub fn log_origin(a: [f32; 4]) -> [f32; 2] {
+    let s = (a[3] * a[3] - 1.0).max(0.0).sqrt();
+    if s < 0.0001 {
+        return [a[0], a[1]];
+    }
+    let k = (a[3] + s).ln() / s;
+    [a[0] * k, a[1] * k]
+}
+
+/// Where `first_person` puts anything behind the eye, which only lights
+/// can be. Triangles are clipped at the near plane before it.
+pub const BEHIND_CAMERA: f32 = 1.0e30;
+
+/// Pixels from the eye to a screen `pix` wide, for the field of view.
+fn focal_length(pix: (f32, f32)) -> f32 {
+    pix.0 / 2.0 / (constants::FIELD_OF_VIEW / 2.0).tan()
+}
+
+/// Screen position and depth of `e`, in units right, up and ahead of the
+/// eye, on a screen `pix` wide and high. Follows `first_person` in
+/// `wgsl_camera`. Depth falls off with `1 / ahead`, so lines stay lines
+/// and clipping against the depth planes stays exact.
+pub fn first_person(e: [f32; 3], pix: (f32, f32)) -> [f32; 3] {
+    if e[2] <= 0.0 {
+        return [0.0, 0.0, BEHIND_CAMERA];
+    }
+    let f = focal_length(pix);
+    [
+        pix.0 / 2.0 + f * e[0] / e[2],
+        pix.1 / 2.0 - f * e[1] / e[2],
+        constants::NEAR_PLANE * constants::NEAR_DISTANCE / e[2],
+    ]
+}
+
+/// What `triangle_normal` gives for the projected corners of a triangle,
+/// worked out from its corners in eye space so it holds for triangles
+/// reaching behind the eye too. Follows `first_person_normal` in
+/// `wgsl_camera`.
+#[cfg(test)]
+pub fn first_person_normal(
+    a: [f32; 3],
+    b: [f32; 3],
+    c: [f32; 3],
+    pix: (f32, f32),
+) -> [f32; 3] {
+    let u = [0, 1, 2].map(|i| b[i] - c[i]);
+    let v = [0, 1, 2].map(|i| a[i] - c[i]);
+    let n = [
+        u[1] * v[2] - u[2] * v[1],
+        u[2] * v[0] - u[0] * v[2],
+        u[0] * v[1] - u[1] * v[0],
+    ];
+    let k = constants::NEAR_PLANE * constants::NEAR_DISTANCE
+        / focal_length(pix);
+    let d = n[0] * a[0] + n[1] * a[1] + n[2] * a[2];
+    let out = [n[0] * k, -n[1] * k, -d];
+    let len = out.iter().map(|x| x * x).sum::<f32>().sqrt();
+    out.map(|x| x / len)
+}
+
+/// What the shaders' `cam` holds, see `wgsl_camera`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct CameraUniform {
+    pub world_to_cam: [[f32; 4]; 4],
+    pub cam_to_screen: [[f32; 4]; 4],
+    pub projection: u32,
+    pub pad: [u32; 3],
+}
+
+/// The `Camera` struct the shaders read `cam` as, `to_model` for each
+/// `ProjectionMode`, and the first-person projection.
+pub fn wgsl_camera() -> String {
+    format!("
+struct Camera {{
+    world_to_cam: mat4x4<f32>,
+    cam_to_screen: mat4x4<f32>,
+    projection: u32,
+}};
+
+let PROJECTION_POINCARE_DISK: u32 = {}u;
+let PROJECTION_KLEIN: u32 = {}u;
+let PROJECTION_UPPER_HALF_PLANE: u32 = {}u;
+let PROJECTION_GNOMONIC: u32 = {}u;
+let PROJECTION_FIRST_PERSON: u32 = {}u;
+let BEHIND_CAMERA: f32 = {:?};
+let NEAR_DISTANCE: f32 = {:?};
+let FOCAL_LENGTH: f32 = {:?};
+
+fn log_origin(a: vec4<f32>) -> vec2<f32> {{
+    // the tangent vector at the camera whose geodesic reaches `a`, //
+    // as long as the distance to it                                //
+    let s = sqrt(max(a.w * a.w - 1.0, 0.0));
+    if s < 0.0001 {{
+        return a.xy;
+    }}
+    return a.xy * log(a.w + s) / s;
+}}
+
+fn to_eye(cam_to_screen: mat4x4<f32>, a: vec4<f32>) -> vec3<f32> {{
+    // right, up and ahead of the first-person eye, for `a` in camera //
+    // coordinates                                                   //
+    return (cam_to_screen * vec4(log_origin(a), a.z - pc.cam_z, 1.0)).xyz;
+}}
+
+fn first_person(e: vec3<f32>) -> vec3<f32> {{
+    // pinhole projection of `e`, right, up and ahead of the eye //
+    if e.z <= 0.0 {{
+        return vec3(0.0, 0.0, BEHIND_CAMERA);
+    }}
+    let f = pc.pix_x / 2.0 * FOCAL_LENGTH;
+    return vec3(
+        pc.pix_x / 2.0 + f * e.x / e.z,
+        pc.pix_y / 2.0 - f * e.y / e.z,
+        pc.near_plane * NEAR_DISTANCE / e.z
+    );
+}}
+
+fn first_person_normal(a: vec3<f32>, b: vec3<f32>, c: vec3<f32>)
+    -> vec3<f32>
+{{
+    // `triangle_normal` of the projected corners, worked out in eye //
+    // space so it holds for triangles reaching behind the eye too   //
+    let n = cross(b - c, a - c);
+    let k = pc.near_plane * NEAR_DISTANCE / (pc.pix_x / 2.0 * FOCAL_LENGTH);
+    return normalize(vec3(n.x * k, -n.y * k, -dot(n, a)));
+}}
+
+fn to_model(projection: u32, a: vec4<f32>) -> vec2<f32> {{
+    // where the hyperboloid point `a` lands in the camera's model //
+    if projection == PROJECTION_POINCARE_DISK {{
+        return a.xy / (1.0 + a.w);
+    }}
+    if projection == PROJECTION_KLEIN || projection == PROJECTION_GNOMONIC {{
+        return a.xy / a.w;
+    }}
+    if projection == PROJECTION_UPPER_HALF_PLANE {{
+        let z = a.xy / (1.0 + a.w);
+        let d = (1.0 - z.x) * (1.0 - z.x) + z.y * z.y;
+        return vec2(-2.0 * z.y, 1.0 - dot(z, z)) / d - vec2(0.0, 1.0);
+    }}
+    if projection == PROJECTION_FIRST_PERSON {{
+        return log_origin(a);
+    }}
+    return a.xy;
+}}
+",
+        ProjectionMode::PoincareDisk as u32,
+        ProjectionMode::Klein as u32,
+        ProjectionMode::UpperHalfPlane as u32,
+        ProjectionMode::Gnomonic as u32,
+        ProjectionMode::FirstPerson as u32,
+        BEHIND_CAMERA,
+        constants::NEAR_DISTANCE,
+        1.0 / (constants::FIELD_OF_VIEW / 2.0).tan(),
+    )
+}
+
+// fraction of the screen's height the disk models fill at the default
+// map scale, `constants::WORLD_SCALE`
+const DISK_FILL: f32 = 0.96;
+
 pub struct Camera {
-    pos: [f32; 4],
-    dir: [f32; 3],
+    pos: [f64; 4],
+    dir: [f64; 3],
+    projection: ProjectionMode,
+    // radians above the heading, only first person looks along it
+    pitch: f32,
+    // what the model's plane is scaled by before `camera_to_screen`
+    map_scale: f32,
 }
 
 impl Camera {
     pub fn new() -> Camera {
-        Self {pos: [0.0, 0.0, 0.0, 1.0], dir: [0.0, 1.0, 0.0]}
+        Self {
+            pos: [0.0, 0.0, 0.0, 1.0],
+            dir: [0.0, 1.0, 0.0],
+            projection: ProjectionMode::Isometric,
+            pitch: -0.3,
+            map_scale: constants::WORLD_SCALE,
+        }
     }
 
-    pub fn set_camera(&mut self, pos: [f32; 4], dir: [f32; 3]) {
-        self.pos = pos;
-        self.dir = dir;
+    /// Puts the camera at `pos` looking along `dir`, evened out onto the
+    /// hyperboloid and each other every time, so drift can't build up.
+    pub fn set_camera(&mut self, pos: [f64; 4], dir: [f64; 3]) {
+        let frame = isometry::frame(pos, dir);
+        self.pos = [frame[(0, 3)], frame[(1, 3)], pos[2], frame[(3, 3)]];
+        self.dir = [frame[(0, 1)], frame[(1, 1)], frame[(3, 1)]];
+    }
 
-        // v this oughta be moved into `player` when that becomes a thing
-        self.make_perpendicular();
+    /// The isometry from camera coordinates to the world's, see
+    /// `isometry::frame`. The height is kept apart in `get_position`.
+    pub fn frame(&self) -> Matrix4<f64> {
+        let [x, y, _, w] = self.pos;
+        isometry::frame([x, y, 0.0, w], self.dir)
     }
 
-    pub fn world_to_camera(&self) -> [[f32; 4]; 4] {
-        let mut out = [[0.0; 4]; 4];
-        let x2y2 = self.pos[0] * self.pos[0] + self.pos[1] * self.pos[1];
-        if x2y2 == 0.0 {
-            out[0][0] = self.dir[1];
-            out[0][1] = self.dir[0];
-            out[1][0] = -self.dir[0];
-            out[1][1] = self.dir[1];
-            out[2][2] = 1.0;
+    /// Puts the camera where `frame` takes the origin, looking where it
+    /// takes +y, at the same height.
+    pub fn set_frame(&mut self, frame: &Matrix4<f64>) {
+        self.set_camera(
+            [frame[(0, 3)], frame[(1, 3)], self.pos[2], frame[(3, 3)]],
+            [frame[(0, 1)], frame[(1, 1)], frame[(3, 1)]],
+        );
+    }
+
+    /// Moves part of the way to where it watches `target`'s frame from,
+    /// once an update, so it trails behind whatever it follows and swings
+    /// around when it turns. First person looks out of its eyes instead.
+    pub fn follow(&mut self, target: &Matrix4<f64>) {
+        if self.projection == ProjectionMode::FirstPerson {
+            return self.snap_to(target);
+        }
+        let frame = isometry::interpolate(
+            &self.frame(), &self.goal(target), constants::FOLLOW_EASING);
+        self.set_frame(&frame);
+    }
+
+    /// Jumps straight to where `follow` is headed.
+    pub fn snap_to(&mut self, target: &Matrix4<f64>) {
+        self.set_frame(&self.goal(target));
+    }
+
+    fn goal(&self, target: &Matrix4<f64>) -> Matrix4<f64> {
+        if self.projection == ProjectionMode::FirstPerson {
+            return *target;
+        }
+        target * isometry::exp(Motion {
+            boost: [0.0, constants::LOOK_AHEAD],
+            turn: 0.0,
+        })
+    }
+
+    /// The isometry from the world's coordinates to the camera's.
+    pub fn world_to_camera(&self) -> Matrix4<f64> {
+        isometry::inverse(&self.frame())
+    }
+
+    /// Takes the model's plane, height and 1, times the map scale, to
+    /// screen coordinates in units of the screen's width. `aspect` is
+    /// the screen's width over its height.
+    pub fn camera_to_screen(&self, aspect: f32) -> [[f32; 4]; 4] {
+        // the top-down modes look straight down, the same way up
+        let top_down = |scale: f32| {
+            let mut out = [[0.0; 4]; 4];
+            out[0][0] = scale;
+            out[1][1] = -scale;
+            out[2][2] = scale;
             out[3][3] = 1.0;
-        } else {
-            let xvyyvx = self.pos[0] * self.dir[1]
-                - self.pos[1] * self.dir[0];
-
-            out[0][0] = (self.pos[0] * self.pos[3] * xvyyvx
-                + self.pos[1] * self.dir[2]) / x2y2;
-            out[0][1] = (-self.pos[1] * xvyyvx
-                + self.pos[0] * self.pos[3] * self.dir[2]) / x2y2;
-            out[0][3] = -self.pos[0];
-
-            out[1][0] = (self.pos[1] * self.pos[3] * xvyyvx
-                - self.pos[0] * self.dir[2]) / x2y2;
-            out[1][1] = (self.pos[0] * xvyyvx
-                + self.pos[1] * self.pos[3] * self.dir[2]) / x2y2;
-            out[1][3] = -self.pos[1];
-
-            out[2][2] = 1.0;
-
-            out[3][0] = -xvyyvx;
-            out[3][1] = -self.dir[2];
-            out[3][3] = self.pos[3];
+            out
+        };
+        match self.projection {
+            ProjectionMode::Isometric => {
+                let mut out = [[0.0; 4]; 4];
+                out[0][0] = (6.0f32).sqrt() / 2.0;
+                out[1][1] = -(2.0f32).sqrt() / 2.0;
+                out[1][2] = -(6.0f32).sqrt() / 3.0;
+                out[2][1] = -1.0;
+                out[2][2] = (3.0f32).sqrt() / 2.0;
+                out[3][3] = 1.0;
+                out
+            }
+            // each runs from -1 to 1 up the screen
+            ProjectionMode::PoincareDisk
+            | ProjectionMode::Klein
+            | ProjectionMode::UpperHalfPlane => top_down(
+                DISK_FILL / 2.0 / aspect / constants::WORLD_SCALE),
+            ProjectionMode::Gnomonic => top_down((6.0f32).sqrt() / 2.0),
+            // takes the log map and height to right, up and ahead of the
+            // eye, which `first_person` then projects
+            ProjectionMode::FirstPerson => {
+                let (sin, cos) = self.pitch.sin_cos();
+                let eye = constants::EYE_HEIGHT;
+                let mut out = [[0.0; 4]; 4];
+                out[0][0] = 1.0;
+                out[1][1] = -sin;
+                out[1][2] = cos;
+                out[2][1] = cos;
+                out[2][2] = sin;
+                out[3][1] = -eye * cos;
+                out[3][2] = -eye * sin;
+                out[3][3] = 1.0;
+                out
+            }
         }
-        out
     }
 
-    pub fn camera_to_screen(&self) -> [[f32; 4]; 4] {
-        let mut out = [[0.0; 4]; 4];
-        out[0][0] = (6.0f32).sqrt() / 2.0;
-        out[1][1] = -(2.0f32).sqrt() / 2.0;
-        out[1][2] = -(6.0f32).sqrt() / 3.0;
-        out[2][1] = -1.0;
-        out[2][2] = (3.0f32).sqrt() / 2.0;
-        out[3][3] = 1.0;
-        out
+    /// Everything the shaders' `cam` needs.
+    pub fn uniform(&self, aspect: f32) -> CameraUniform {
+        CameraUniform {
+            world_to_cam: self.world_to_camera().cast::<f32>().into(),
+            cam_to_screen: self.camera_to_screen(aspect),
+            projection: self.projection as u32,
+            pad: [0; 3],
+        }
     }
 
-    pub fn get_position(&self) -> &[f32; 4] { &self.pos }
+    pub fn get_projection(&self) -> ProjectionMode { self.projection }
 
-    pub fn get_direction(&self) -> &[f32; 3] { &self.dir }
+    /// The map scale the shaders' `map_scale` gets on a screen `aspect`
+    /// times as wide as it is high. First person doesn't zoom.
+    pub fn map_scale(&self, aspect: f32) -> f32 {
+        if self.projection == ProjectionMode::FirstPerson {
+            return constants::WORLD_SCALE;
+        }
+        let (min, max) = self.map_scale_range(aspect);
+        self.map_scale.clamp(min, max)
+    }
 
-    fn divergence(&self) -> (f32, f32, f32) {(
-        self.dir[2] * self.pos[3]
-      - self.dir[0] * self.pos[0]
-      - self.dir[1] * self.pos[1],
+    /// Scales the map by `factor`, as far as `map_scale_range` allows.
+    pub fn zoom(&mut self, factor: f32, aspect: f32) {
+        let (min, max) = self.map_scale_range(aspect);
+        self.map_scale = (self.map_scale(aspect) * factor).clamp(min, max);
+    }
 
-        self.dir[2] * self.dir[2]
-      - self.dir[0] * self.dir[0]
-      - self.dir[1] * self.dir[1],
+    /// How far out and in the map can be zoomed. Out stops where the
+    /// screen's corners would reach past `constants::STREAM_RADIUS`, the
+    /// disk models stop once the whole disk is on screen.
+    fn map_scale_range(&self, aspect: f32) -> (f32, f32) {
+        let r = constants::STREAM_RADIUS as f32;
+        // how far the screen's corners are from its middle in the
+        // model's plane, at a map scale of 1
+        let min = match self.projection {
+            ProjectionMode::Isometric => {
+                let x = 0.5 / ((6.0f32).sqrt() / 2.0);
+                let y = 0.5 / aspect / ((2.0f32).sqrt() / 2.0);
+                x.hypot(y) / r.sinh()
+            }
+            ProjectionMode::Gnomonic => {
+                (0.5f32).hypot(0.5 / aspect) / ((6.0f32).sqrt() / 2.0)
+                    / r.tanh()
+            }
+            _ => constants::WORLD_SCALE,
+        };
+        (min, constants::MAX_MAP_SCALE.max(min))
+    }
 
-        self.pos[3] * self.pos[3]
-      - self.pos[0] * self.pos[0]
-      - self.pos[1] * self.pos[1]
-    )}
 
-    fn normalize(&mut self) {
-        let p_mag = (self.pos[3] * self.pos[3]
-            - self.pos[0] * self.pos[0]
-            - self.pos[1] * self.pos[1]).abs();
+    /// Looks `angle` radians further up, without changing the heading.
+    pub fn tilt(&mut self, angle: f32) {
+        self.pitch = (self.pitch + angle)
+            .clamp(-constants::MAX_PITCH, constants::MAX_PITCH);
+    }
 
-        self.pos = [
-            self.pos[0] / p_mag.sqrt(),
-            self.pos[1] / p_mag.sqrt(),
-            self.pos[2],
-            self.pos[3] / p_mag.sqrt(),
-        ];
+    /// The point `distance` along the heading, at the same height.
+    pub fn ahead(&self, distance: f64) -> [f64; 4] {
+        let (sinh, cosh) = (distance.sinh(), distance.cosh());
+        let (p, d) = (&self.pos, &self.dir);
+        [
+            p[0] * cosh + d[0] * sinh,
+            p[1] * cosh + d[1] * sinh,
+            p[2],
+            p[3] * cosh + d[2] * sinh,
+        ]
+    }
+
+    pub fn set_projection(&mut self, projection: ProjectionMode) {
+        self.projection = projection;
+    }
+
+    pub fn get_position(&self) -> &[f64; 4] { &self.pos }
+}
 
-        let v_mag = (self.dir[2] * self.dir[2]
-            - self.dir[0] * self.dir[0]
-            - self.dir[1] * self.dir[1]).abs();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::tile::{ self, permutation::IDENTITY };
 
-        self.dir = [
-            self.dir[0] / v_mag.sqrt(),
-            self.dir[1] / v_mag.sqrt(),
-            self.dir[2] / v_mag.sqrt(),
-        ];
+    /// The point `distance` out from the camera, `angle` round from +x.
+    fn point(distance: f32, angle: f32) -> [f32; 4] {
+        let (sin, cos) = angle.sin_cos();
+        [distance.sinh() * cos, distance.sinh() * sin, 0.0, distance.cosh()]
     }
 
-    fn make_perpendicular(&mut self) {
-        let (dot, v_div, p_div) = self.divergence();
+    /// Points all around the camera, out to where f32 still tells the
+    /// disk models' images apart from the rim.
+    fn points() -> impl Iterator<Item = [f32; 4]> {
+        (0..=50).flat_map(|d| (0..36).map(move |a|
+            point(d as f32 * 0.1, a as f32 / 18.0 * std::f32::consts::PI)))
+    }
+
+    #[test]
+    fn long_walks_stay_on_the_hyperboloid() {
+        let mut camera = Camera::new();
+        for step in isometry::random_motions(0.02).take(100_000) {
+            camera.set_frame(&(camera.frame() * isometry::exp(step)));
+            let (p, d) = (camera.pos, camera.dir);
+            let norm = p[3] * p[3] - p[0] * p[0] - p[1] * p[1];
+            let speed = d[0] * d[0] + d[1] * d[1] - d[2] * d[2];
+            let across = d[2] * p[3] - d[0] * p[0] - d[1] * p[1];
+            assert!((norm - 1.0).abs() < 1e-12 * p[3] * p[3], "{:?}", p);
+            assert!((speed - 1.0).abs() < 1e-12 * p[3] * p[3], "{:?}", d);
+            assert!(across.abs() < 1e-12 * p[3] * p[3], "{:?} {:?}", p, d);
+        }
+        // and got somewhere f32 would have lost its way
+        assert!(camera.pos[3] > 10.0, "{:?}", camera.pos);
+    }
+
+    #[test]
+    fn follows_around_a_quarter_turn() {
+        let mut camera = Camera::new();
+        let target = isometry::exp(Motion {
+            boost: [0.0; 2],
+            turn: std::f64::consts::FRAC_PI_2,
+        });
+        // looking ahead of the target, which now faces -x
+        let goal = target * isometry::exp(Motion {
+            boost: [0.0, constants::LOOK_AHEAD],
+            turn: 0.0,
+        });
+        camera.follow(&target);
+        // only part of the way there after one update
+        assert!(camera.frame()[(0, 1)] > -0.5);
+        for _ in 0..200 {
+            camera.follow(&target);
+        }
+        let off = camera.frame() - goal;
+        assert!(off.abs().max() < 1e-4, "{}", off);
+
+        camera.set_projection(ProjectionMode::FirstPerson);
+        camera.follow(&Matrix4::identity());
+        assert_eq!(camera.get_position(), &[0.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn zoom_stays_in_range() {
+        let mut camera = Camera::new();
+        camera.zoom(100.0, 16.0 / 9.0);
+        assert_eq!(camera.map_scale(16.0 / 9.0), constants::MAX_MAP_SCALE);
+        camera.set_projection(ProjectionMode::PoincareDisk);
+        camera.zoom(0.01, 16.0 / 9.0);
+        assert_eq!(camera.map_scale(16.0 / 9.0), constants::WORLD_SCALE);
+    }
 
-        let thresh = 0.00005 * self.pos[3];
-        if ((v_div + 1.0).abs() > thresh)
-        || ((p_div - 1.0).abs() > thresh) {
-            self.normalize();
+    #[test]
+    fn disk_models_stay_in_the_disk() {
+        for a in points() {
+            for mode in [ProjectionMode::PoincareDisk, ProjectionMode::Klein] {
+                let [x, y] = mode.to_model(a);
+                assert!(x.hypot(y) < 1.0, "{:?} {:?}", mode, a);
+            }
         }
-        if dot.abs() > thresh {
-            let new_vect = [
-                self.dir[0] - dot * self.pos[0],
-                self.dir[1] - dot * self.pos[1],
-                self.dir[2] - dot * self.pos[3]
-            ];
-            let mag = new_vect[2] * new_vect[2]
-                - new_vect[0] * new_vect[0]
-                - new_vect[1] * new_vect[1];
-            self.dir = [
-                new_vect[0] / (mag.abs().sqrt()),
-                new_vect[1] / (mag.abs().sqrt()),
-                new_vect[2] / (mag.abs().sqrt()),
-            ];
+    }
+
+    #[test]
+    fn klein_is_the_poincare_disk_stretched() {
+        for a in points() {
+            let p = ProjectionMode::PoincareDisk.to_model(a);
+            let k = ProjectionMode::Klein.to_model(a);
+            let stretch = 2.0 / (1.0 + p[0] * p[0] + p[1] * p[1]);
+            for i in 0..2 {
+                assert!((k[i] - p[i] * stretch).abs() < 1e-5, "{:?}", a);
+            }
+        }
+    }
+
+    #[test]
+    fn klein_keeps_tile_edges_straight() {
+        let world_to_cam = isometry::exp(Motion {
+            boost: [0.3, -0.5],
+            turn: 0.7,
+        });
+        let codes = std::iter::once(IDENTITY).chain(IDENTITY.neighbors());
+        for code in codes {
+            let to_cam = world_to_cam * code.get_matrix();
+            let corners = tile::CORNERS.map(|c|
+                to_cam * nalgebra::Vector4::from(c));
+            for i in 0..4 {
+                let (a, b) = (corners[i], corners[(i + 1) % 4]);
+                let model = |t: f64| {
+                    // the geodesic from `a` to `b` is where the plane
+                    // through them and the centre cuts the hyperboloid
+                    let v = a * (1.0 - t) + b * t;
+                    let norm = (v.w * v.w - v.x * v.x - v.y * v.y).sqrt();
+                    let v = (v / norm).cast::<f32>();
+                    ProjectionMode::Klein.to_model([v.x, v.y, v.z, v.w])
+                };
+                let (start, end) = (model(0.0), model(1.0));
+                let along = [end[0] - start[0], end[1] - start[1]];
+                let length = along[0].hypot(along[1]);
+                for t in 1..10 {
+                    let m = model(t as f64 / 10.0);
+                    let off = (m[0] - start[0]) * along[1]
+                        - (m[1] - start[1]) * along[0];
+                    assert!((off / length).abs() < 1e-5, "{:?}", m);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn half_plane_sits_above_its_line() {
+        let mode = ProjectionMode::UpperHalfPlane;
+        assert_eq!(mode.to_model([0.0, 0.0, 0.0, 1.0]), [0.0, 0.0]);
+        for a in points() {
+            assert!(mode.to_model(a)[1] > -1.0, "{:?}", a);
         }
     }
 }
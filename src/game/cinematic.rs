@@ -0,0 +1,301 @@
+use std::fmt;
+use std::fs::{ self, OpenOptions };
+use std::io::{ self, Write };
+
+use nalgebra::{ Matrix4, Vector4 };
+use crate::constants;
+use crate::game::camera::Camera;
+use crate::game::isometry::{ self, Motion };
+use crate::game::tile::Tile;
+use crate::game::tile::permutation::GroupElt;
+
+/// Where the camera was at one point of a cinematic: which tile of the
+/// surface it was over, and where on that tile, looking which way.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Keyframe {
+    /// the tile's id, the same for every copy of it
+    pub tile: u32,
+    /// the camera's `frame`, in the tile's coordinates
+    pub local: Matrix4<f64>,
+}
+
+impl Keyframe {
+    /// Where `camera` is, over whichever of `tiles` is closest.
+    pub fn of_camera(camera: &Camera, tiles: &mut [Tile]) -> Option<Keyframe> {
+        let pos = *camera.get_position();
+        let tile = tiles.iter_mut().min_by(|a, b|
+            a.distance_from(&pos).total_cmp(&b.distance_from(&pos)))?;
+        Some(Keyframe {
+            tile: tile.get_code(),
+            local: isometry::inverse(&tile.get_mat()) * camera.frame(),
+        })
+    }
+
+    /// Reads a line the way `Display` writes it.
+    fn parse(line: &str) -> Option<Keyframe> {
+        let mut words = line.split_whitespace();
+        let tile = words.next()?.parse().ok()?;
+        let mut number = || words.next()?.parse::<f64>().ok();
+        let local = isometry::exp(Motion {
+            boost: [number()?, number()?],
+            turn: number()?,
+        });
+        words.next().is_none().then_some(Keyframe { tile, local })
+    }
+}
+
+/// The tile id, then the motion taking the tile's middle to the camera:
+/// its boost along x and y, and its turn.
+impl fmt::Display for Keyframe {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let m = isometry::log(&self.local);
+        write!(f, "{} {} {} {}", self.tile, m.boost[0], m.boost[1], m.turn)
+    }
+}
+
+/// The keyframes in the file at `path`, one a line, leaving out blank lines
+/// and `#` comments. A file that isn't there has none.
+pub fn load(path: &std::path::Path) -> Result<Vec<Keyframe>, String> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(
+            format!("couldn't read {}: {}", path.display(), e)
+        ),
+    };
+    text.lines().enumerate()
+        .filter(|(_, line)|
+            !line.trim().is_empty() && !line.trim().starts_with('#'))
+        .map(|(i, line)| Keyframe::parse(line).ok_or_else(|| format!(
+            "{}:{}: wants a tile id, a boost along x and y and a turn, \
+                got {:?}",
+            path.display(), i + 1, line
+        )))
+        .collect()
+}
+
+/// Adds `keyframe` to the end of the file at `path`, making it if need be.
+pub fn append(path: &std::path::Path, keyframe: &Keyframe) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", keyframe)
+}
+
+/// Keyframes joined up by geodesics, ready to play.
+pub struct Path {
+    keyframes: Vec<Keyframe>,
+    // from each keyframe to the next, in the camera's coordinates
+    motions: Vec<Motion>,
+}
+
+impl Path {
+    /// Goes from each keyframe to the closest copy of the next one's tile,
+    /// which can be no more than `constants::KEYFRAME_REACH` away.
+    pub fn new(keyframes: Vec<Keyframe>) -> Result<Path, String> {
+        if keyframes.is_empty() {
+            return Err("no keyframes to play".to_owned());
+        }
+        let motions = keyframes.windows(2).enumerate().map(|(i, pair)| {
+            let next = nearest_copy(&pair[0], &pair[1]).ok_or_else(||
+                format!("keyframe {}'s tile is too far from keyframe {}'s",
+                    i + 1, i))?;
+            Ok(isometry::log(&(isometry::inverse(&pair[0].local) * next)))
+        }).collect::<Result<_, String>>()?;
+        Ok(Path { keyframes, motions })
+    }
+
+    /// Where it starts.
+    pub fn start(&self) -> &Keyframe { &self.keyframes[0] }
+
+    /// How many frames playing it takes, both ends included.
+    pub fn frames(&self) -> u32 {
+        self.motions.len() as u32 * constants::KEYFRAME_FRAMES + 1
+    }
+}
+
+/// Where `to`'s camera is in the coordinates of `from`'s tile, on the copy
+/// of its tile that puts it closest to `from`'s camera.
+fn nearest_copy(from: &Keyframe, to: &Keyframe) -> Option<Matrix4<f64>> {
+    let origin = Vector4::new(0.0, 0.0, 0.0, 1.0);
+    let distance = |a: Vector4<f64>, b: Vector4<f64>|
+        (a.w * b.w - a.xy().dot(&b.xy())).max(1.0).acosh();
+    let camera = from.local * origin;
+
+    // every tile around `from`'s, out as far as the reach
+    let mut codes = vec![GroupElt::from_id(from.tile)];
+    let mut centers = vec![origin];
+    let mut best: Option<(f64, Matrix4<f64>)> = None;
+    let mut i = 0;
+    while i < codes.len() {
+        let mut code = codes[i];
+        i += 1;
+        if code.get_id() == to.tile {
+            let there = code.get_matrix() * to.local;
+            let d = distance(camera, there * origin);
+            if best.is_none_or(|(closest, _)| d < closest) {
+                best = Some((d, there));
+            }
+        }
+        for next in code.neighbors() {
+            let center = next.get_matrix() * origin;
+            // the middles of different tiles are acosh(2) apart
+            if distance(center, origin) < constants::KEYFRAME_REACH
+                && centers.iter().all(|c| distance(*c, center) > 0.5)
+            {
+                centers.push(center);
+                codes.push(next);
+            }
+        }
+    }
+    best.map(|(_, there)| there)
+}
+
+/// Playing a `Path` back, one frame an update.
+pub struct Playback {
+    path: Path,
+    // how many frames it's been through
+    frame: u32,
+}
+
+impl Playback {
+    pub fn new(path: Path) -> Playback {
+        Self { path, frame: 0 }
+    }
+
+    /// Moves `camera` on to the next frame, or gives `false` once every
+    /// frame has been. It goes from one keyframe to the next along the
+    /// geodesic, slowing down at each end. Every keyframe is found again
+    /// among `tiles` on the way past, wherever re-centering has moved
+    /// them, so the way isn't lost.
+    pub fn step(&mut self, camera: &mut Camera, tiles: &mut [Tile]) -> bool {
+        if self.frame >= self.path.frames() {
+            return false;
+        }
+        let n = constants::KEYFRAME_FRAMES;
+        let (segment, i) = ((self.frame / n) as usize, self.frame % n);
+        if i == 0 {
+            snap(camera, tiles, &self.path.keyframes[segment]);
+        } else {
+            let ease = |i: u32| {
+                let t = i as f64 / n as f64;
+                t * t * (3.0 - 2.0 * t)
+            };
+            let step = self.path.motions[segment].scale(ease(i) - ease(i - 1));
+            camera.set_frame(&(camera.frame() * isometry::exp(step)));
+        }
+        self.frame += 1;
+        true
+    }
+}
+
+/// Puts `camera` on `keyframe`, over the copy of its tile in `tiles` that's
+/// closest. Leaves it be if there isn't one.
+fn snap(camera: &mut Camera, tiles: &mut [Tile], keyframe: &Keyframe) {
+    let pos = *camera.get_position();
+    let tile = tiles.iter_mut()
+        .filter_map(|tile| (tile.get_code() == keyframe.tile).then_some(tile))
+        .min_by(|a, b|
+            a.distance_from(&pos).total_cmp(&b.distance_from(&pos)));
+    if let Some(tile) = tile {
+        camera.set_frame(&(tile.get_mat() * keyframe.local));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::tile::permutation::IDENTITY;
+
+    /// `center` and the two rings of tiles around it.
+    fn around(center: GroupElt) -> Vec<Tile> {
+        let mut codes = vec![center];
+        for code in center.neighbors() {
+            codes.push(code);
+            codes.extend(code.neighbors());
+        }
+        codes.into_iter().map(Tile::new).collect()
+    }
+
+    /// A tile two over from the first one.
+    fn far() -> GroupElt {
+        IDENTITY.neighbors()[1].neighbors()[2]
+    }
+
+    fn keyframes() -> Vec<Keyframe> {
+        let local = |boost, turn| isometry::exp(Motion { boost, turn });
+        vec![
+            Keyframe { tile: 0, local: local([0.2, -0.1], 1.0) },
+            Keyframe { tile: far().get_id(), local: local([-0.3, 0.4], 0.0) },
+        ]
+    }
+
+    fn play(path: Path, tiles: &mut [Tile]) -> Vec<Matrix4<f64>> {
+        let mut camera = Camera::new();
+        let mut playback = Playback::new(path);
+        let mut out = Vec::new();
+        while playback.step(&mut camera, tiles) {
+            out.push(camera.frame());
+        }
+        out
+    }
+
+    fn assert_close(a: &Matrix4<f64>, b: &Matrix4<f64>) {
+        assert!((a - b).abs().max() < 1.0e-3, "{} {}", a, b);
+    }
+
+    #[test]
+    fn keyframes_survive_the_file() {
+        for keyframe in keyframes() {
+            let line = keyframe.to_string();
+            let back = Keyframe::parse(&line).unwrap();
+            assert_eq!(back.tile, keyframe.tile);
+            assert_close(&back.local, &keyframe.local);
+        }
+        assert!(Keyframe::parse("12 0.5 0.1").is_none());
+        assert!(Keyframe::parse("12 0.5 0.1 0.0 3").is_none());
+    }
+
+    #[test]
+    fn goes_to_the_nearest_copy() {
+        let mut next = IDENTITY.neighbors()[3];
+        let keyframes = vec![
+            Keyframe { tile: 0, local: Matrix4::identity() },
+            Keyframe { tile: next.get_id(), local: Matrix4::identity() },
+        ];
+        let path = Path::new(keyframes).unwrap();
+        assert_close(&isometry::exp(path.motions[0]), &next.get_matrix());
+    }
+
+    #[test]
+    fn plays_from_end_to_end() {
+        let keyframes = keyframes();
+        let path = Path::new(keyframes.clone()).unwrap();
+        let frames = path.frames();
+        let out = play(path, &mut around(IDENTITY));
+        assert_eq!(out.len() as u32, frames);
+        assert_close(&out[0], &keyframes[0].local);
+        let far = far().get_matrix();
+        assert!(far[(3, 3)] > 2.5);
+        assert_close(out.last().unwrap(), &(far * keyframes[1].local));
+        // geodesics cross tile edges without jumping
+        for pair in out.windows(2) {
+            let step = isometry::inverse(&pair[0]) * pair[1];
+            assert!(step[(3, 3)] < 1.01, "{}", step);
+        }
+    }
+
+    #[test]
+    fn crosses_recentering() {
+        let path = || Path::new(keyframes()).unwrap();
+        let before = play(path(), &mut around(IDENTITY));
+        // the map re-centered on a neighbour, the way `Game::update` does
+        let back = isometry::inverse(&IDENTITY.neighbors()[1].get_matrix());
+        let mut tiles = around(IDENTITY);
+        for tile in tiles.iter_mut() {
+            tile.move_by(&back);
+        }
+        let after = play(path(), &mut tiles);
+        for (a, b) in before.iter().zip(after.iter()) {
+            assert_close(&(back * a), b);
+        }
+    }
+}
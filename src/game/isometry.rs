@@ -0,0 +1,270 @@
+use nalgebra::{ Matrix4, RealField };
+
+// Isometries of H² × E are the 4x4 matrices tiles are placed with, see
+// `Tile::get_mat`: elements of SO⁺(2,1) on x, y and w, leaving the height
+// z alone. Two tiles a few steps apart already multiply out to entries in
+// the hundreds that mostly cancel, which is why the world is kept in f64.
+
+/// A motion of H² at constant speed, as an element of so(2,1): a boost
+/// along x and y and a turn to the left, per unit of time. Anything that
+/// moves this way for a while ends up moved by `exp` of it.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Motion {
+    pub boost: [f64; 2],
+    pub turn: f64,
+}
+
+impl Motion {
+    /// The same motion, `t` times as fast.
+    pub fn scale(self, t: f64) -> Motion {
+        Motion { boost: self.boost.map(|b| b * t), turn: self.turn * t }
+    }
+
+    fn matrix(self) -> Matrix4<f64> {
+        let [a, b] = self.boost;
+        let turn = self.turn;
+        Matrix4::new(
+            0.0, -turn, 0.0, a,
+            turn, 0.0, 0.0, b,
+            0.0, 0.0, 0.0, 0.0,
+            a, b, 0.0, 0.0,
+        )
+    }
+
+    fn from_matrix(x: &Matrix4<f64>) -> Motion {
+        Motion {
+            boost: [x[(0, 3)], x[(1, 3)]],
+            turn: x[(1, 0)],
+        }
+    }
+}
+
+/// Where the motion `m` gets to after a unit of time. Its matrix `X` has
+/// `X³ = k X`, so the series comes down to `I + f X + g X²`.
+pub fn exp(m: Motion) -> Matrix4<f64> {
+    let x = m.matrix();
+    let k = x[(0, 3)] * x[(0, 3)] + x[(1, 3)] * x[(1, 3)]
+        - x[(1, 0)] * x[(1, 0)];
+    let s = k.abs().sqrt();
+    let (f, g) = if s < 1.0e-4 {
+        // the series, this close to parabolic
+        (1.0 + k / 6.0, 0.5 + k / 24.0)
+    } else if k > 0.0 {
+        (s.sinh() / s, (s.cosh() - 1.0) / k)
+    } else {
+        (s.sin() / s, (1.0 - s.cos()) / -k)
+    };
+    Matrix4::identity() + x * f + x * x * g
+}
+
+/// The undoing of the isometry `m`. The metric is `diag(1, 1, 1, -1)`, so
+/// it's `m` transposed with w's row and column flipped around.
+pub fn inverse<T: RealField + Copy>(m: &Matrix4<T>) -> Matrix4<T> {
+    let mut out = m.transpose();
+    for i in 0..3 {
+        out[(i, 3)] = -out[(i, 3)];
+        out[(3, i)] = -out[(3, i)];
+    }
+    out
+}
+
+/// The isometry taking the origin to `pos` and +y to the unit tangent
+/// `dir` there, as x, y and w, keeping the height. The two are evened out
+/// onto the hyperboloid and each other first, so small drift doesn't
+/// build up.
+pub fn frame(pos: [f64; 4], dir: [f64; 3]) -> Matrix4<f64> {
+    let dot = |a: [f64; 3], b: [f64; 3]|
+        a[0] * b[0] + a[1] * b[1] - a[2] * b[2];
+    let p = [pos[0], pos[1], pos[3]];
+    let p = p.map(|x| x / (-dot(p, p)).sqrt());
+    let d = dir;
+    let along = dot(d, p);
+    let d = [0, 1, 2].map(|i| d[i] + along * p[i]);
+    let d = d.map(|x| x / dot(d, d).sqrt());
+    // a quarter turn right of `d`, so the frame keeps its orientation
+    let r = [
+        p[2] * d[1] - p[1] * d[2],
+        p[0] * d[2] - p[2] * d[0],
+        p[0] * d[1] - p[1] * d[0],
+    ];
+    Matrix4::new(
+        r[0], d[0], 0.0, p[0],
+        r[1], d[1], 0.0, p[1],
+        0.0, 0.0, 1.0, 0.0,
+        r[2], d[2], 0.0, p[2],
+    )
+}
+
+/// The motion `exp` takes to `m`, turning no more than half way around.
+/// Half turns have two, and which one comes out isn't defined.
+pub fn log(m: &Matrix4<f64>) -> Motion {
+    // `exp(-X)` only flips `X`'s sign, so this is `f X`
+    let odd = (m - inverse(m)) / 2.0;
+    let c = (m[(0, 0)] + m[(1, 1)] + m[(3, 3)] - 1.0) / 2.0;
+    if c >= 1.0 {
+        let s = c.acosh();
+        let f = if s < 1.0e-4 { 1.0 } else { s.sinh() / s };
+        return Motion::from_matrix(&(odd / f));
+    }
+    let s = c.max(-1.0).acos();
+    if s < 2.0 {
+        return Motion::from_matrix(&(odd * (s / s.sin())));
+    }
+
+    // close to a half turn `sin` runs out, so find the point `p` it
+    // turns around from `X² = -s² (I + p pᵀ η)` on x, y and w instead
+    let even = (m + inverse(m)) / 2.0 - Matrix4::identity();
+    let p = even.column(3) / (1.0 - s.cos());
+    let w = (p[3] + 1.0).max(0.0).sqrt();
+    let p = [p[0] / w, p[1] / w, w];
+    let about = Motion { boost: [p[1], -p[0]], turn: p[2] };
+    // the odd part is `sin s` of turning around `p`, which says which way
+    // it turns and, unlike `acos`, how far this close to a half turn
+    let j = about.matrix();
+    let sin = odd.component_mul(&j).sum() / j.component_mul(&j).sum();
+    about.scale(sin.atan2(c))
+}
+
+/// The isometry `t` of the way from `a` to `b`, moving at a constant speed
+/// the whole way, like slerp does for rotations. `0` gives `a` and `1`
+/// gives `b`.
+pub fn interpolate(a: &Matrix4<f64>, b: &Matrix4<f64>, t: f64)
+    -> Matrix4<f64>
+{
+    a * exp(log(&(inverse(a) * b)).scale(t))
+}
+
+/// Small motions every which way, up to `size` in each part and the same
+/// ones every time, for walking around in tests.
+#[cfg(test)]
+pub fn random_motions(size: f64) -> impl Iterator<Item = Motion> {
+    // xorshift, from a fixed seed
+    let mut seed = 0x2545_f491_u32;
+    let mut random = move || {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        (seed as f64 / u32::MAX as f64 * 2.0 - 1.0) * size
+    };
+    std::iter::from_fn(move || Some(Motion {
+        boost: [random(), random()],
+        turn: random(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::tile::Tile;
+    use crate::game::tile::permutation::{ IDENTITY, ROTATION, TRANSLATION };
+
+    // further apart than tiles the game has loaded at once ever get
+    const REACH: f64 = 8.0;
+
+    /// Every tile placement up to `len` steps from the first tile, as far
+    /// as `REACH`.
+    fn placements(len: usize) -> Vec<Matrix4<f64>> {
+        let mut out = Vec::new();
+        for word in 0..1u32 << len {
+            let mut code = IDENTITY;
+            for i in 0..len {
+                let step = if word >> i & 1 == 0 { ROTATION } else {
+                    TRANSLATION
+                };
+                code = code.multiply(&step);
+            }
+            out.push(Tile::new(code).get_mat());
+        }
+        out.retain(|m| m[(3, 3)] < REACH.cosh());
+        out
+    }
+
+    /// `placements` paired up with others no more than `REACH` away.
+    fn pairs(len: usize, skip: usize)
+        -> Vec<(Matrix4<f64>, Matrix4<f64>)>
+    {
+        let all = placements(len);
+        let mut out: Vec<_> = all.iter().copied()
+            .zip(all.iter().copied().skip(skip))
+            .filter(|(a, b)| {
+                (inverse(a) * b)[(3, 3)] < REACH.cosh()
+            })
+            .collect();
+        out.extend(all.iter().map(|m| (Matrix4::identity(), *m)));
+        out
+    }
+
+    fn assert_close(a: &Matrix4<f64>, b: &Matrix4<f64>) {
+        // entries grow like cosh of how far the tile is
+        let scale = a.abs().max().max(1.0);
+        assert!((a - b).abs().max() < 1.0e-9 * scale, "{} {}", a, b);
+    }
+
+    #[test]
+    fn exp_undoes_log() {
+        for m in placements(7) {
+            assert_close(&exp(log(&m)), &m);
+        }
+    }
+
+    #[test]
+    fn log_undoes_exp() {
+        for i in 0..200 {
+            let t = i as f64 * 0.37;
+            let m = Motion {
+                boost: [t.sin() * 1.5, (t * 1.3).cos()],
+                turn: (t * 0.7).sin() * 3.0,
+            };
+            let back = log(&exp(m));
+            let err = (0..2).map(|j| (back.boost[j] - m.boost[j]).abs())
+                .fold((back.turn - m.turn).abs(), f64::max);
+            assert!(err < 1.0e-9, "{:?} {:?}", m, back);
+        }
+    }
+
+    #[test]
+    fn stays_an_isometry() {
+        let eta = Matrix4::from_diagonal(&[1.0, 1.0, 1.0, -1.0].into());
+        for (a, b) in pairs(5, 11) {
+            for t in [0.0, 0.25, 0.5, 0.9, 1.0] {
+                let m = interpolate(&a, &b, t);
+                assert_close(&(m.transpose() * eta * m), &eta);
+                assert!(m[(3, 3)] > 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn interpolation_reaches_both_ends() {
+        for (a, b) in pairs(6, 7) {
+            assert_close(&interpolate(&a, &b, 0.0), &a);
+            assert_close(&interpolate(&a, &b, 1.0), &b);
+        }
+    }
+
+    #[test]
+    fn interpolation_keeps_a_steady_pace() {
+        for (a, b) in pairs(6, 3) {
+            // each step is the same motion as the last
+            let steps: Vec<_> = (0..=4)
+                .map(|i| interpolate(&a, &b, i as f64 / 4.0))
+                .collect();
+            let step = |a: &Matrix4<f64>, b: &Matrix4<f64>| inverse(a) * b;
+            let first = step(&steps[0], &steps[1]);
+            for pair in steps.windows(2) {
+                assert_close(&step(&pair[0], &pair[1]), &first);
+            }
+        }
+    }
+
+    #[test]
+    fn turning_and_boosting_match_the_tiles() {
+        let quarter = Motion {
+            boost: [0.0; 2],
+            turn: -std::f64::consts::FRAC_PI_2,
+        };
+        assert_close(&exp(quarter), &ROTATION.get_matrix());
+        let step = Motion { boost: [0.0, 2.0f64.acosh()], turn: 0.0 };
+        assert_close(&exp(step), &TRANSLATION.get_matrix());
+    }
+}
@@ -0,0 +1,77 @@
+/// Surface properties shared by everything drawn with the same material id.
+/// Uploaded as-is into the material table the shaders index.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Material {
+    /// multiplies the vertex color
+    pub diffuse: [f32; 4],
+    /// rgb + intensity, added regardless of lights
+    pub emissive: [f32; 4],
+    pub specular: f32,
+    pub exponent: f32,
+    pub flags: u32,
+    pub pad: u32,
+}
+
+/// Skip lighting entirely and show the surface color as-is.
+pub const UNLIT: u32 = 1;
+
+pub const GROUND: u32 = 0;
+pub const WATER: u32 = 1;
+pub const CRYSTAL: u32 = 2;
+pub const MARKER: u32 = 3;
+
+pub const MATERIALS: [Material; 4] = [
+    // GROUND
+    Material {
+        diffuse: [1.0, 1.0, 1.0, 1.0],
+        emissive: [0.0; 4],
+        specular: 0.05,
+        exponent: 8.0,
+        flags: 0,
+        pad: 0,
+    },
+    // WATER
+    Material {
+        diffuse: [0.3, 0.5, 0.9, 1.0],
+        emissive: [0.0; 4],
+        specular: 0.9,
+        exponent: 64.0,
+        flags: 0,
+        pad: 0,
+    },
+    // CRYSTAL
+    Material {
+        diffuse: [0.8, 0.5, 1.0, 1.0],
+        emissive: [0.6, 0.3, 1.0, 0.6],
+        specular: 0.5,
+        exponent: 32.0,
+        flags: 0,
+        pad: 0,
+    },
+    // MARKER
+    Material {
+        diffuse: [1.0, 0.9, 0.2, 1.0],
+        emissive: [0.0; 4],
+        specular: 0.0,
+        exponent: 1.0,
+        flags: UNLIT,
+        pad: 0,
+    },
+];
+
+/// WGSL side of `Material`, for shaders reading the material table.
+pub fn wgsl_material() -> String {
+    format!("
+struct Material {{
+    diffuse: vec4<f32>,
+    emissive: vec4<f32>,
+    specular: f32,
+    exponent: f32,
+    flags: u32,
+    pad: u32,
+}};
+
+let MATERIAL_UNLIT: u32 = {}u;
+", UNLIT)
+}
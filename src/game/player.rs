@@ -0,0 +1,108 @@
+use nalgebra::Matrix4;
+use crate::game::isometry::{ self, Motion };
+
+/// What the keys move around. The camera only watches it, see
+/// `Camera::follow`.
+pub struct Player {
+    // where it stands and faces, as the isometry from its own coordinates,
+    // where it's at the origin looking along +y, to the world's
+    frame: Matrix4<f64>,
+}
+
+impl Player {
+    pub fn new() -> Player {
+        Self { frame: Matrix4::identity() }
+    }
+
+    pub fn frame(&self) -> &Matrix4<f64> { &self.frame }
+
+    /// Where it's standing, as the camera's `get_position` has it.
+    pub fn get_position(&self) -> [f64; 4] {
+        [0, 1, 2, 3].map(|i| self.frame[(i, 3)])
+    }
+
+    /// Walks `forward` along its heading and `left` across it at once,
+    /// straight along the geodesic between, carrying the heading along.
+    pub fn walk(&mut self, forward: f64, left: f64) {
+        self.set_frame(self.frame * isometry::exp(Motion {
+            boost: [-left, forward],
+            turn: 0.0,
+        }));
+    }
+
+    /// Turns `angle` radians to the left, on the spot.
+    pub fn turn(&mut self, angle: f64) {
+        self.set_frame(self.frame * isometry::exp(Motion {
+            boost: [0.0; 2],
+            turn: angle,
+        }));
+    }
+
+    /// Moves it along with the rest of the world by the isometry `m`, the
+    /// way re-centering does.
+    pub fn move_by(&mut self, m: &Matrix4<f64>) {
+        self.set_frame(m * self.frame);
+    }
+
+    /// Puts it where `frame` takes the origin, facing where it takes +y.
+    pub fn set_frame(&mut self, frame: Matrix4<f64>) {
+        // evened out every time, so rounding can't pile up into something
+        // that's not an isometry
+        self.frame = isometry::frame(
+            [0, 1, 2, 3].map(|i| frame[(i, 3)]),
+            [0, 1, 3].map(|i| frame[(i, 1)]),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn distance(a: [f64; 4], b: [f64; 4]) -> f64 {
+        (a[3] * b[3] - a[0] * b[0] - a[1] * b[1]).max(1.0).acosh()
+    }
+
+    #[test]
+    fn walks_in_a_square() {
+        let mut player = Player::new();
+        for _ in 0..4 {
+            player.walk(0.5, 0.0);
+            player.turn(std::f64::consts::FRAC_PI_2);
+        }
+        // the corners come out short of right angles in H², so it misses
+        let end = player.get_position();
+        assert!(distance(end, [0.0, 0.0, 0.0, 1.0]) > 0.05);
+        // but walking straight back undoes walking straight out
+        let start = player.get_position();
+        player.walk(1.0, 0.3);
+        player.walk(-1.0, -0.3);
+        assert!(distance(player.get_position(), start) < 1.0e-4);
+    }
+
+    #[test]
+    fn long_walks_stay_isometries() {
+        let eta = Matrix4::from_diagonal(&[1.0, 1.0, 1.0, -1.0].into());
+        let mut player = Player::new();
+        for step in isometry::random_motions(0.02).take(100_000) {
+            player.walk(step.boost[1], -step.boost[0]);
+            player.turn(step.turn);
+            let f = player.frame();
+            // the columns stay unit length and at right angles
+            let off = (f.transpose() * eta * f - eta).abs().max();
+            assert!(off < 1e-12 * f[(3, 3)] * f[(3, 3)], "{}", f);
+        }
+    }
+
+    #[test]
+    fn left_is_left_of_the_heading() {
+        let mut player = Player::new();
+        player.turn(0.4);
+        let mut turned = Player::new();
+        turned.turn(0.4 + std::f64::consts::FRAC_PI_2);
+        turned.walk(0.7, 0.0);
+        player.walk(0.0, 0.7);
+        assert!(distance(player.get_position(), turned.get_position())
+            < 1.0e-4);
+    }
+}
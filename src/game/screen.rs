@@ -1,66 +1,191 @@
 use crate::game::tile::Tile;
 
+use std::path::Path;
+
+use nalgebra::Matrix4;
+
 use winit::window::Window;
 
-use self::renderer::{ Renderer };
-use crate::constants;
+use self::renderer::{ Renderer, Scene };
+use self::shaders::PcBinding;
+use crate::config::{ Config, RasterBackend, Scaling };
 
 //mod texture;
 pub mod renderer;
+mod cull;
+mod gbuffer;
+mod geometry;
+mod hardware;
+mod minimap;
+mod offscreen;
+mod raster;
+#[cfg(test)]
+mod reference;
+mod shaders;
+mod shadow;
 
 pub struct Screen {
-    surface: wgpu::Surface,
+    // `None` when rendering headless
+    window: Option<WindowTarget>,
     device: wgpu::Device,
     queue: wgpu::Queue,
-    config: wgpu::SurfaceConfiguration,
+    format: wgpu::TextureFormat,
     pub size: winit::dpi::PhysicalSize<u32>,
+    scaling: Scaling,
     border: [f32; 2],
 
     renderer: Renderer,
 }
 
-impl Screen {
-    pub async fn new(window: &Window) -> Screen {
-        let mut limits = wgpu::Limits::default();
-        limits.max_push_constant_size = 128;
+/// The window's surface, and how it's configured.
+struct WindowTarget {
+    surface: wgpu::Surface,
+    config: wgpu::SurfaceConfiguration,
+}
 
-        let size = window.inner_size();
+/// The downlevel flags `raster` needs. Lighting is a compute pass either
+/// way, and the hardware backend reads meshes from storage buffers in its
+/// vertex shader.
+fn required_downlevel(raster: RasterBackend) -> wgpu::DownlevelFlags {
+    match raster {
+        RasterBackend::Compute => wgpu::DownlevelFlags::COMPUTE_SHADERS,
+        RasterBackend::Hardware => wgpu::DownlevelFlags::COMPUTE_SHADERS
+            | wgpu::DownlevelFlags::VERTEX_STORAGE,
+    }
+}
 
-        let goal_aspect_ratio = constants::SCREEN_PIXELS.0 as f32
-            / constants::SCREEN_PIXELS.1 as f32;
-        let actual_aspect_ratio = size.width as f32
-            / size.height as f32;
+/// The limits to open a device with, given what the adapter has. These
+/// are the downlevel defaults, so GL and GLES adapters can give them, with
+/// what the pipelines need on top.
+fn required_limits(adapter: &wgpu::Limits, pc: PcBinding)
+    -> Result<wgpu::Limits, String>
+{
+    let mut limits = wgpu::Limits::downlevel_defaults()
+        .using_resolution(adapter.clone());
+    // the binning pass has eight storage buffers bound at once
+    limits.max_storage_buffers_per_shader_stage = 8;
+    if pc == PcBinding::Push {
+        limits.max_push_constant_size = shaders::PUSH_CONSTANTS_SIZE;
+    }
+    let mut missing = Vec::new();
+    limits.check_limits_with_fail_fn(adapter, false, |name, want, have|
+        missing.push(format!("{} (needs {}, has {})", name, want, have)));
+    if missing.is_empty() {
+        Ok(limits)
+    } else {
+        Err(format!("the graphics adapter is missing {}",
+            missing.join(", ")))
+    }
+}
 
-        let mut border = [0f32; 2];
-        if actual_aspect_ratio > goal_aspect_ratio{
-            border[0] = (actual_aspect_ratio
-                    - goal_aspect_ratio)
-                / actual_aspect_ratio;
-        } else {
-            border[1] = ((1.0 / actual_aspect_ratio)
-                    - (1.0 / goal_aspect_ratio))
-                * actual_aspect_ratio;
+/// The preferred adapter, or a software one when there isn't any.
+async fn request_adapter(
+    instance: &wgpu::Instance,
+    surface: Option<&wgpu::Surface>,
+) -> Option<wgpu::Adapter> {
+    for force_fallback_adapter in [false, true] {
+        let adapter = instance.request_adapter(
+            &wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::default(),
+                compatible_surface: surface,
+                force_fallback_adapter,
+            },
+        ).await;
+        if adapter.is_some() {
+            return adapter;
         }
+    }
+    None
+}
+
+/// Picks an adapter, falling back to a software one, and opens it with
+/// whatever it can do for `pc`. Fails if it can't draw with `raster`.
+async fn open_device(
+    instance: &wgpu::Instance,
+    surface: Option<&wgpu::Surface>,
+    raster: RasterBackend,
+) -> Result<(wgpu::Adapter, wgpu::Device, wgpu::Queue, PcBinding), String> {
+    let adapter = request_adapter(instance, surface).await;
+    let adapter = adapter.ok_or("no graphics adapter found")?;
+    let downlevel = adapter.get_downlevel_capabilities().flags;
+    let needed = required_downlevel(raster);
+    if !downlevel.contains(needed) {
+        return Err(format!(
+            "{} can't draw with the {:?} backend, it's missing {:?}",
+            adapter.get_info().name, raster, needed - downlevel,
+        ));
+    }
+
+    // push constants are the quick way to get `pc` into the shaders,
+    // but GL and some software adapters don't have them
+    let pc = if adapter.features().contains(wgpu::Features::PUSH_CONSTANTS)
+    {
+        PcBinding::Push
+    } else {
+        PcBinding::Uniform
+    };
+    let features = match pc {
+        PcBinding::Push => wgpu::Features::PUSH_CONSTANTS,
+        PcBinding::Uniform => wgpu::Features::empty(),
+    };
+    let limits = required_limits(&adapter.limits(), pc)?;
+    log::info!("using {:?}, pc through {:?}", adapter.get_info(), pc);
+
+    let (device, queue) = adapter.request_device(
+        &wgpu::DeviceDescriptor {
+            features,
+            limits,
+            label: None,
+        },
+        None,
+    ).await.map_err(|e| format!("couldn't open the graphics device: {}", e))?;
+    Ok((adapter, device, queue, pc))
+}
+
+/// Letterboxing for `resolution` pixels shown in a window of `size`, as
+/// the fraction of each side left empty.
+fn borders(
+    size: winit::dpi::PhysicalSize<u32>,
+    resolution: (u32, u32),
+    scaling: Scaling,
+) -> [f32; 2] {
+    let scale = (size.width / resolution.0).min(size.height / resolution.1);
+    if scaling == Scaling::Integer && scale > 0 {
+        return [
+            1.0 - (scale * resolution.0) as f32 / size.width as f32,
+            1.0 - (scale * resolution.1) as f32 / size.height as f32,
+        ];
+    }
+
+    let goal_aspect_ratio = resolution.0 as f32
+        / resolution.1 as f32;
+    let actual_aspect_ratio = size.width as f32
+        / size.height as f32;
+
+    let mut border = [0f32; 2];
+    if actual_aspect_ratio > goal_aspect_ratio{
+        border[0] = (actual_aspect_ratio
+                - goal_aspect_ratio)
+            / actual_aspect_ratio;
+    } else {
+        border[1] = ((1.0 / actual_aspect_ratio)
+                - (1.0 / goal_aspect_ratio))
+            * actual_aspect_ratio;
+    }
+    border
+}
+
+impl Screen {
+    pub async fn new(window: &Window, settings: &Config)
+        -> Result<Screen, String>
+    {
+        let size = window.inner_size();
 
         // Stupid gpu setup
         let instance = wgpu::Instance::new(wgpu::Backends::all());
         let surface = unsafe { instance.create_surface(window) };
-        let adapter = instance.request_adapter(
-            &wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::default(),
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            },
-        ).await.unwrap();
-        let (device, queue) = adapter.request_device(
-            &wgpu::DeviceDescriptor {
-                features: wgpu::Features::PUSH_CONSTANTS |
-                    wgpu::Features::CLEAR_TEXTURE,
-                limits,
-                label: None,
-            },
-            None,
-        ).await.unwrap();
+        let (adapter, device, queue, pc) =
+            open_device(&instance, Some(&surface), settings.raster).await?;
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
             format: surface.get_supported_formats(&adapter)[0],
@@ -70,66 +195,240 @@ impl Screen {
         };
         surface.configure(&device, &config);
 
-        let renderer = Renderer::new(&device, &config);
+        let format = config.format;
+        let mut renderer = Renderer::new(
+            &device, format, settings.raster, settings.resolution, pc);
+        renderer.show_minimap(settings.minimap);
 
-        Self {
-            surface,
+        Ok(Self {
+            window: Some(WindowTarget { surface, config }),
             device,
             queue,
-            config,
+            format,
             size,
-            border,
+            scaling: settings.scaling,
+            border: borders(size, settings.resolution, settings.scaling),
 
             renderer,
-        }
+        })
+    }
+
+    /// A screen with nothing to present to, which only takes screenshots.
+    /// Works on a software adapter with no display.
+    pub async fn headless(settings: &Config) -> Result<Screen, String> {
+        let instance = wgpu::Instance::new(wgpu::Backends::all());
+        let (_, device, queue, pc) =
+            open_device(&instance, None, settings.raster).await?;
+        let format = offscreen::FORMAT;
+        let mut renderer = Renderer::new(
+            &device, format, settings.raster, settings.resolution, pc);
+        renderer.show_minimap(settings.minimap);
+
+        Ok(Self {
+            window: None,
+            device,
+            queue,
+            format,
+            size: winit::dpi::PhysicalSize::new(
+                settings.resolution.0,
+                settings.resolution.1,
+            ),
+            scaling: settings.scaling,
+            border: [0.0; 2],
+
+            renderer,
+        })
     }
 
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
         if new_size.width > 0 && new_size.height > 0 {
             self.size = new_size;
-            self.config.width = new_size.width;
-            self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config);
-
-            let goal_aspect_ratio = constants::SCREEN_PIXELS.0 as f32
-                / constants::SCREEN_PIXELS.1 as f32;
-            let actual_aspect_ratio = new_size.width as f32
-                / new_size.height as f32;
-
-            let mut border = [0f32; 2];
-            if actual_aspect_ratio > goal_aspect_ratio{
-                border[0] = (actual_aspect_ratio
-                        - goal_aspect_ratio)
-                    / actual_aspect_ratio;
-            } else {
-                border[1] = ((1.0 / actual_aspect_ratio)
-                        - (1.0 / goal_aspect_ratio))
-                    * actual_aspect_ratio;
+            if let Some(window) = &mut self.window {
+                window.config.width = new_size.width;
+                window.config.height = new_size.height;
+                window.surface.configure(&self.device, &window.config);
+                self.border = borders(
+                    new_size, self.resolution(), self.scaling);
             }
-            self.border = border;
         }
     }
 
+    /// The size frames are drawn at, before they're scaled to the window.
+    pub fn resolution(&self) -> (u32, u32) {
+        self.renderer.resolution()
+    }
+
+    /// Changes the internal resolution. Takes effect from the next frame.
+    pub fn set_resolution(&mut self, resolution: (u32, u32)) {
+        self.renderer.set_resolution(&self.device, resolution);
+        if self.window.is_some() {
+            self.border = borders(self.size, resolution, self.scaling);
+        } else {
+            self.size = winit::dpi::PhysicalSize::new(
+                resolution.0, resolution.1);
+        }
+    }
+
+    pub fn scaling(&self) -> Scaling {
+        self.scaling
+    }
+
+    pub fn set_scaling(&mut self, scaling: Scaling) {
+        self.scaling = scaling;
+        if self.window.is_some() {
+            self.border = borders(self.size, self.resolution(), scaling);
+        }
+    }
+
+    pub fn minimap_shown(&self) -> bool {
+        self.renderer.minimap_shown()
+    }
+
+    pub fn show_minimap(&mut self, shown: bool) {
+        self.renderer.show_minimap(shown);
+    }
+
+    /// Every tile has to be acquired before it's drawn, and released
+    /// before it's dropped.
+    pub fn acquire_tile(&mut self, tile: &mut Tile) {
+        self.renderer.acquire_tile(tile);
+    }
+
+    pub fn release_tile(&mut self, tile: &mut Tile) {
+        self.renderer.release_tile(tile);
+    }
+
+    pub fn pool_stats(&self) -> geometry::PoolStats {
+        self.renderer.pool_stats()
+    }
+
+    /// Tiles drawn and culled in the last frame.
+    pub fn cull_stats(&self) -> cull::CullStats {
+        self.renderer.cull_stats()
+    }
+
+    /// Triangles binned, and any left out, a frame or so ago.
+    pub fn bin_stats(&self) -> raster::BinStats {
+        self.renderer.bin_stats()
+    }
+
+    /// Draws a frame to the window, with the minimap around `player`, the
+    /// player's frame. Headless screens have nowhere to put it, so they
+    /// skip it.
     pub fn render(
         &self,
         camera: &crate::game::camera::Camera,
-        tiles: &mut [Tile]
+        player: &Matrix4<f64>,
+        tiles: &[Tile]
     ) -> Result<(), wgpu::SurfaceError> {
+        let window = match &self.window {
+            Some(window) => window,
+            None => return Ok(()),
+        };
+        let output = window.surface.get_current_texture()?;
+        let view = output.texture.create_view(
+            &wgpu::TextureViewDescriptor::default());
         self.renderer.render(
             &self.device,
-            &self.surface, 
-            &self.queue, 
-            camera, 
-            tiles,
+            &self.queue,
+            &view,
+            &Scene { camera, player, tiles },
             &self.border
-        )
+        );
+        output.present();
+        Ok(())
     }
 
-    pub fn updade_tile_pos(&self, tile: &mut Tile) {
-        self.queue.write_buffer(
-            tile.get_pos().unwrap(),
-            0,
-            bytemuck::cast_slice(tile.get_mat().as_slice())
+    /// Draws a frame at the internal resolution into a texture of its own,
+    /// and reads it back as rows of rgba bytes.
+    pub fn capture(
+        &self,
+        camera: &crate::game::camera::Camera,
+        player: &Matrix4<f64>,
+        tiles: &[Tile],
+    ) -> Vec<u8> {
+        let target = offscreen::Target::new(
+            &self.device, self.format, self.resolution());
+        self.renderer.render(
+            &self.device,
+            &self.queue,
+            &target.view(),
+            &Scene { camera, player, tiles },
+            &[0.0; 2]
         );
+        target.read_rgba(&self.device, &self.queue)
+    }
+
+    /// `capture`, saved to `path` as a png.
+    pub fn screenshot(
+        &self,
+        camera: &crate::game::camera::Camera,
+        player: &Matrix4<f64>,
+        tiles: &[Tile],
+        path: &Path,
+    ) -> Result<(), png::EncodingError> {
+        offscreen::save_png(
+            path, self.resolution(), &self.capture(camera, player, tiles))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use winit::dpi::PhysicalSize;
+
+    #[test]
+    fn fit_fills_one_side() {
+        let border = borders(PhysicalSize::new(1000, 360), (640, 360),
+            Scaling::Fit);
+        assert!((border[0] - 0.36).abs() < 1e-6);
+        assert_eq!(border[1], 0.0);
+        let border = borders(PhysicalSize::new(1280, 720), (640, 360),
+            Scaling::Fit);
+        assert_eq!(border, [0.0, 0.0]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn integer_scaling_keeps_whole_pixels() {
+        // 2x is the most that fits, leaving 1000 - 2 * 320 across
+        let border = borders(PhysicalSize::new(1000, 500), (320, 180),
+            Scaling::Integer);
+        assert_eq!(border, [1.0 - 640.0 / 1000.0, 1.0 - 360.0 / 500.0]);
+
+        // a window smaller than the resolution can only shrink it
+        let small = PhysicalSize::new(500, 300);
+        assert_eq!(
+            borders(small, (640, 360), Scaling::Integer),
+            borders(small, (640, 360), Scaling::Fit),
+        );
+    }
+
+    /// What a GLES adapter with room for the binning pass reports.
+    fn gles() -> wgpu::Limits {
+        wgpu::Limits {
+            max_storage_buffers_per_shader_stage: 8,
+            max_texture_dimension_2d: 4096,
+            ..wgpu::Limits::downlevel_defaults()
+        }
+    }
+
+    #[test]
+    fn downlevel_adapters_are_enough() {
+        let limits = required_limits(&gles(), PcBinding::Uniform).unwrap();
+        assert!(limits.check_limits(&gles()));
+        // big windows still fit
+        assert_eq!(limits.max_texture_dimension_2d, 4096);
+    }
+
+    #[test]
+    fn missing_limits_are_errors() {
+        let adapter = wgpu::Limits::downlevel_defaults();
+        let e = required_limits(&adapter, PcBinding::Uniform).unwrap_err();
+        assert!(e.contains("max_storage_buffers_per_shader_stage"), "{}", e);
+        // push constants have to fit as well
+        let e = required_limits(&gles(), PcBinding::Push).unwrap_err();
+        assert!(e.contains("max_push_constant_size"), "{}", e);
+        let adapter = wgpu::Limits { max_push_constant_size: 128, ..gles() };
+        assert!(required_limits(&adapter, PcBinding::Push).is_ok());
+    }
+}
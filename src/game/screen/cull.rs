@@ -0,0 +1,257 @@
+use nalgebra::{ Matrix4, Vector4 };
+use crate::constants;
+use crate::game::camera::{ self, Camera, ProjectionMode, BEHIND_CAMERA };
+use crate::game::tile::CORNERS;
+use crate::game::tile::mapdata::Mesh;
+
+/// Points around a mesh, in the tile's own coordinates, whose box on
+/// screen holds the box of all its vertices.
+#[derive(Clone, Debug)]
+pub struct Hull {
+    points: Vec<[f32; 4]>,
+}
+
+/// Where each of the mesh's vertices is.
+fn positions(mesh: &Mesh) -> impl Iterator<Item = [f32; 4]> + '_ {
+    // vertices are a position and a color, 4 floats each
+    mesh.vertices.chunks_exact(32).map(|vertex|
+        [0, 1, 2, 3].map(|i| f32::from_le_bytes(
+            vertex[4 * i..4 * i + 4].try_into().unwrap()
+        ))
+    )
+}
+
+impl Hull {
+    /// The tile's sides at the lowest and highest of the mesh's heights.
+    /// Every projection is smooth and one-to-one, so nothing inside the
+    /// sides lands further out on screen than they do. Most models bend
+    /// them, so each is sampled often enough that what bows out between
+    /// samples can't reach the screen on its own.
+    pub fn of_mesh(mesh: &Mesh) -> Hull {
+        let (low, high) = positions(mesh).fold(
+            (f32::INFINITY, f32::NEG_INFINITY),
+            |(low, high), v| (low.min(v[2]), high.max(v[2])),
+        );
+
+        let mut points = Vec::new();
+        for (i, a) in CORNERS.iter().enumerate() {
+            let b = CORNERS[(i + 1) % 4];
+            for step in 0..constants::HULL_STEPS {
+                // the sides are straight in the Klein model
+                let t = step as f64 / constants::HULL_STEPS as f64;
+                let k = [0, 1].map(|j|
+                    a[j] / a[3] * (1.0 - t) + b[j] / b[3] * t);
+                let w = 1.0 / (1.0 - k[0] * k[0] - k[1] * k[1]).sqrt();
+                for z in [low, high] {
+                    points.push([
+                        (k[0] * w) as f32, (k[1] * w) as f32, z, w as f32,
+                    ]);
+                }
+            }
+        }
+        Hull { points }
+    }
+}
+
+/// Tiles drawn and skipped in the last frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CullStats {
+    pub drawn: u32,
+    pub culled: u32,
+}
+
+/// Everything `w_to_c` uses to get a vertex onto the screen.
+pub struct Projection {
+    pub world_to_cam: [[f32; 4]; 4],
+    pub cam_to_screen: [[f32; 4]; 4],
+    pub mode: ProjectionMode,
+    pub cam_z: f32,
+    pub map_scale: f32,
+    pub pix: (f32, f32),
+    pub near_plane: f32,
+    pub far_plane: f32,
+}
+
+// matrices are uploaded as columns, the same way WGSL reads them
+fn mul(m: &[[f32; 4]; 4], v: [f32; 4]) -> [f32; 4] {
+    [0, 1, 2, 3].map(|r| (0..4).map(|c| m[c][r] * v[c]).sum())
+}
+
+impl Projection {
+    /// What the renderer draws `camera`'s view with, at `size` pixels.
+    pub fn new(camera: &Camera, size: (u32, u32)) -> Projection {
+        let aspect = size.0 as f32 / size.1 as f32;
+        Projection {
+            world_to_cam: camera.world_to_camera().cast::<f32>().into(),
+            cam_to_screen: camera.camera_to_screen(aspect),
+            mode: camera.get_projection(),
+            cam_z: camera.get_position()[2] as f32,
+            map_scale: camera.map_scale(aspect),
+            pix: (size.0 as f32, size.1 as f32),
+            near_plane: constants::NEAR_PLANE,
+            far_plane: constants::FAR_PLANE,
+        }
+    }
+
+    /// Screen x, y and depth of `v`, in the coordinates of `tile`. Follows
+    /// `w_to_c` in binning.wgsl.
+    pub fn project(&self, tile: &Matrix4<f32>, v: [f32; 4]) -> [f32; 3] {
+        let t = tile * Vector4::from(v);
+        let a = mul(&self.world_to_cam, [t.x, t.y, t.z, t.w]);
+        if self.mode == ProjectionMode::FirstPerson {
+            return camera::first_person(self.eye(tile, v), self.pix);
+        }
+        let m = self.mode.to_model(a);
+        let b = [m[0], m[1], a[2] - self.cam_z, 1.0]
+            .map(|x| x * self.map_scale);
+        let c = mul(&self.cam_to_screen, b);
+        [
+            c[0] * self.pix.0 + self.pix.0 / 2.0,
+            c[1] * self.pix.0 + self.pix.1 / 2.0,
+            c[2] * self.pix.0,
+        ]
+    }
+
+    /// Right, up and ahead of the first-person eye, for `v` in the
+    /// coordinates of `tile`. Follows `w_to_eye` in binning.wgsl.
+    pub fn eye(&self, tile: &Matrix4<f32>, v: [f32; 4]) -> [f32; 3] {
+        let t = tile * Vector4::from(v);
+        let a = mul(&self.world_to_cam, [t.x, t.y, t.z, t.w]);
+        let m = camera::log_origin(a);
+        let e = mul(&self.cam_to_screen, [m[0], m[1], a[2] - self.cam_z, 1.0]);
+        [e[0], e[1], e[2]]
+    }
+
+    /// `false` only if none of the mesh can land on screen between the
+    /// depth planes. Triangles are drawn straight between their projected
+    /// corners, so every one lands inside the box around them.
+    pub fn visible(&self, tile: &Matrix4<f32>, hull: &Hull) -> bool {
+        let mut min = [f32::INFINITY; 3];
+        let mut max = [f32::NEG_INFINITY; 3];
+        for point in hull.points.iter() {
+            let p = self.project(tile, *point);
+            if p[2] == BEHIND_CAMERA {
+                // the sides stop bounding what's ahead of the eye once
+                // they reach behind it, as they do around where it stands
+                return true;
+            }
+            for i in 0..3 {
+                min[i] = min[i].min(p[i]);
+                max[i] = max[i].max(p[i]);
+            }
+        }
+        max[0] >= 0.0 && min[0] <= self.pix.0
+            && max[1] >= 0.0 && min[1] <= self.pix.1
+            && max[2] >= self.far_plane && min[2] <= self.near_plane
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::tile::mapdata::meshes;
+
+    fn projection() -> Projection {
+        Projection::new(&Camera::new(), constants::SCREEN_PIXELS)
+    }
+
+    /// Slides the tile `d` along x.
+    fn translation(d: f32) -> Matrix4<f32> {
+        Matrix4::new(
+            d.cosh(), 0.0, 0.0, d.sinh(),
+            0.0, 1.0, 0.0, 0.0,
+            0.0, 0.0, 1.0, 0.0,
+            d.sinh(), 0.0, 0.0, d.cosh(),
+        )
+    }
+
+    /// Turns the tile by `a` about the origin.
+    fn rotation(a: f32) -> Matrix4<f32> {
+        Matrix4::new(
+            a.cos(), -a.sin(), 0.0, 0.0,
+            a.sin(), a.cos(), 0.0, 0.0,
+            0.0, 0.0, 1.0, 0.0,
+            0.0, 0.0, 0.0, 1.0,
+        )
+    }
+
+    #[test]
+    fn origin_lands_mid_screen() {
+        let origin = [0.0, 0.0, 0.0, 1.0];
+        let p = projection().project(&Matrix4::identity(), origin);
+        assert_eq!(p, [
+            constants::SCREEN_PIXELS.0 as f32 / 2.0,
+            constants::SCREEN_PIXELS.1 as f32 / 2.0,
+            0.0,
+        ]);
+    }
+
+    #[test]
+    fn far_tiles_are_culled() {
+        let hull = Hull::of_mesh(&meshes()[0]);
+        assert!(projection().visible(&Matrix4::identity(), &hull));
+        assert!(!projection().visible(&translation(4.0), &hull));
+        assert!(!projection().visible(&translation(-4.0), &hull));
+    }
+
+    #[test]
+    fn culling_never_drops_a_visible_vertex() {
+        let size = constants::SCREEN_PIXELS;
+        let aspect = size.0 as f32 / size.1 as f32;
+        let mesh = &meshes()[0];
+        let hull = Hull::of_mesh(mesh);
+        for mode in ProjectionMode::ALL {
+            for zoom in [0.0, 1.0, 100.0] {
+                let mut camera = Camera::new();
+                camera.set_projection(mode);
+                camera.zoom(zoom, aspect);
+                let proj = Projection::new(&camera, size);
+                for (turn, step) in (0..16).flat_map(|turn|
+                    (0..=40).map(move |step| (turn, step)))
+                {
+                    let tile = rotation(turn as f32 * 0.4)
+                        * translation(step as f32 * 0.05);
+                    let any_on_screen = positions(mesh).any(|v| {
+                        let p = proj.project(&tile, v);
+                        (0.0..=proj.pix.0).contains(&p[0])
+                            && (0.0..=proj.pix.1).contains(&p[1])
+                    });
+                    if any_on_screen {
+                        assert!(proj.visible(&tile, &hull),
+                            "{:?} zoomed {} at {} {}", mode, zoom, turn, step);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn zooming_out_stops_at_the_streamed_tiles() {
+        let size = constants::SCREEN_PIXELS;
+        let aspect = size.0 as f32 / size.1 as f32;
+        let r = constants::STREAM_RADIUS as f32;
+        for mode in [ProjectionMode::Isometric, ProjectionMode::Gnomonic] {
+            let mut camera = Camera::new();
+            camera.set_projection(mode);
+            camera.zoom(0.0, aspect);
+            let proj = Projection::new(&camera, size);
+            // the circle the streamed tiles reach, in half screens from
+            // the middle
+            let circle: Vec<[f32; 2]> = (0..3600).map(|i| {
+                let (sin, cos) = (i as f32 / 1800.0 * std::f32::consts::PI)
+                    .sin_cos();
+                let v = [r.sinh() * cos, r.sinh() * sin, 0.0, r.cosh()];
+                let p = proj.project(&Matrix4::identity(), v);
+                [
+                    (p[0] / proj.pix.0 * 2.0 - 1.0).abs(),
+                    (p[1] / proj.pix.1 * 2.0 - 1.0).abs(),
+                ]
+            }).collect();
+            // never inside the screen, and touching its corners
+            assert!(circle.iter().all(|p| p[0].max(p[1]) > 0.999), "{:?}",
+                mode);
+            assert!(circle.iter().any(|p|
+                (p[0] - 1.0).hypot(p[1] - 1.0) < 0.01), "{:?}", mode);
+        }
+    }
+}
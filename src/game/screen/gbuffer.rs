@@ -0,0 +1,261 @@
+/// How a channel's `u32` is packed, which decides the WGSL helpers emitted
+/// for it.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// z-value between the far and near planes (24)
+    Depth,
+    /// red, green, blue, alpha (8 each)
+    Rgba8,
+    /// octahedral x, y (16 each)
+    Octahedral,
+    /// plain integer (32)
+    Uint,
+}
+
+pub struct Channel {
+    pub name: &'static str,
+    pub encoding: Encoding,
+}
+
+/// The G-buffer is one `u32` per channel per pixel, with a pixel's channels
+/// next to each other in this order.
+pub const LAYOUT: [Channel; 5] = [
+    Channel { name: "depth", encoding: Encoding::Depth },
+    Channel { name: "color", encoding: Encoding::Rgba8 },
+    Channel { name: "normal", encoding: Encoding::Octahedral },
+    Channel { name: "material", encoding: Encoding::Uint },
+    Channel { name: "emissive", encoding: Encoding::Rgba8 },
+];
+
+pub const G_BUFFER_NUMS: u32 = LAYOUT.len() as u32;
+
+/// Bytes in the G-buffer at `size`.
+pub fn g_buffer_size(size: (u32, u32)) -> u64 {
+    size.0 as u64 * size.1 as u64
+        * 4 // size of u32
+        * G_BUFFER_NUMS as u64 // data points per pixel
+}
+
+/// Indexing and packing functions for every channel in `LAYOUT`. Shaders
+/// touching the G-buffer get this prepended, and need a `pc.pix_x`.
+pub fn wgsl_prelude() -> String {
+    let mut out = String::new();
+
+    out += "// functions for indexing the g-buffer //\n";
+    for (i, channel) in LAYOUT.iter().enumerate() {
+        out += &format!(
+            "fn {}(x: u32, y: u32) -> u32 \
+            {{ return (x + y * u32(pc.pix_x)) * {}u + {}u; }}\n",
+            channel.name, G_BUFFER_NUMS, i,
+        );
+    }
+
+    for encoding in [Encoding::Depth, Encoding::Rgba8, Encoding::Octahedral] {
+        if LAYOUT.iter().any(|c| c.encoding == encoding) {
+            out += match encoding {
+                Encoding::Depth => WGSL_DEPTH,
+                Encoding::Rgba8 => WGSL_RGBA8,
+                Encoding::Octahedral => WGSL_OCTAHEDRAL,
+                Encoding::Uint => "",
+            };
+        }
+    }
+
+    out
+}
+
+/// The hardware backend renders each channel into its own texture of this
+/// format, then packs them into the buffer.
+pub const TARGET_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Uint;
+
+/// Fragment output with one render target per channel, in `LAYOUT` order.
+pub fn wgsl_targets() -> String {
+    let mut out = String::from("struct GBufferTargets {\n");
+    for (i, channel) in LAYOUT.iter().enumerate() {
+        out += &format!("    @location({}) {}: u32,\n", i, channel.name);
+    }
+    out + "};\n"
+}
+
+/// Bindings for the hardware backend's render targets and the entry point
+/// copying them into the G-buffer. Goes after `pack.wgsl`, which declares
+/// the buffer.
+pub fn wgsl_pack() -> String {
+    let mut out = String::new();
+    for (i, channel) in LAYOUT.iter().enumerate() {
+        out += &format!(
+            "@group(1) @binding({}) var {}_target: texture_2d<u32>;\n",
+            i, channel.name,
+        );
+    }
+
+    out += "
+@compute
+@workgroup_size(16, 16)
+fn pack_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= u32(pc.pix_x) || id.y >= u32(pc.pix_y) {
+        return;
+    }
+    let p = vec2<i32>(id.xy);
+";
+    for channel in LAYOUT.iter() {
+        out += &format!(
+            "    g_buffer.values[{0}(id.x, id.y)] = \
+            textureLoad({0}_target, p, 0).r;\n",
+            channel.name,
+        );
+    }
+    out + "}\n"
+}
+
+const WGSL_DEPTH: &str = "
+fn z_to_depth_buf(z: f32) -> u32 {
+    return u32((z - pc.far_plane)
+        / (pc.near_plane - pc.far_plane)
+        * 16777215.0);
+}
+
+fn depth_buf_to_z(num: u32) -> f32 {
+    return f32(num) / 16777215.0
+        * (pc.near_plane - pc.far_plane)
+        + pc.far_plane;
+}
+";
+
+const WGSL_RGBA8: &str = "
+fn color_to_int(col: vec4<f32>) -> u32 {
+    // takes a color vector and converts it to an integer //
+    return u32(255.0 * clamp(col.a, 0.0, 1.0))
+        + (u32(255.0 * clamp(col.b, 0.0, 1.0)) << 8u)
+        + (u32(255.0 * clamp(col.g, 0.0, 1.0)) << 16u)
+        + (u32(255.0 * clamp(col.r, 0.0, 1.0)) << 24u);
+}
+
+fn int_to_color(in: u32) -> vec4<f32> {
+    // takes an integer and converts it to a color vector //
+    return vec4<f32>(
+        f32(in >> 24u) / 255.0,
+        f32((in >> 16u) % 256u) / 255.0,
+        f32((in >> 8u) % 256u) / 255.0,
+        f32(in % 256u) / 255.0,
+    );
+}
+";
+
+const WGSL_OCTAHEDRAL: &str = "
+fn sign_not_zero(v: vec2<f32>) -> vec2<f32> {
+    return select(vec2(-1.0), vec2(1.0), v >= vec2(0.0));
+}
+
+fn normal_to_int(norm: vec3<f32>) -> u32 {
+    // folds the normal onto an octahedron, then packs xy //
+    var p = norm.xy / (abs(norm.x) + abs(norm.y) + abs(norm.z));
+    if norm.z < 0.0 {
+        p = (1.0 - abs(p.yx)) * sign_not_zero(p);
+    }
+    return (u32(65535.0 * clamp((p.x + 1.0) / 2.0, 0.0, 1.0)) << 16u)
+        + u32(65535.0 * clamp((p.y + 1.0) / 2.0, 0.0, 1.0));
+}
+
+fn int_to_normal(num: u32) -> vec3<f32> {
+    let x = (f32(num >> 16u) / 65535.0) * 2.0 - 1.0;
+    let y = (f32(num % (1u << 16u)) / 65535.0) * 2.0 - 1.0;
+    var norm = vec3<f32>(x, y, 1.0 - abs(x) - abs(y));
+    if norm.z < 0.0 {
+        norm = vec3(
+            (1.0 - abs(norm.yx)) * sign_not_zero(norm.xy),
+            norm.z
+        );
+    }
+    return normalize(norm);
+}
+";
+
+/* #region CPU REFERENCE */
+// Rust versions of the packing functions above.
+
+#[cfg(test)]
+pub mod cpu {
+    use crate::constants;
+
+    pub fn z_to_depth_buf(z: f32) -> u32 {
+        ((z - constants::FAR_PLANE)
+            / (constants::NEAR_PLANE - constants::FAR_PLANE)
+            * 16777215.0) as u32
+    }
+
+    pub fn depth_buf_to_z(num: u32) -> f32 {
+        num as f32 / 16777215.0
+            * (constants::NEAR_PLANE - constants::FAR_PLANE)
+            + constants::FAR_PLANE
+    }
+
+    pub fn color_to_int(col: [f32; 4]) -> u32 {
+        let byte = |c: f32| (255.0 * c.clamp(0.0, 1.0)) as u32;
+        byte(col[3])
+            + (byte(col[2]) << 8)
+            + (byte(col[1]) << 16)
+            + (byte(col[0]) << 24)
+    }
+
+    pub fn int_to_color(num: u32) -> [f32; 4] {
+        [24, 16, 8, 0].map(|shift| ((num >> shift) % 256) as f32 / 255.0)
+    }
+
+    fn sign_not_zero(v: f32) -> f32 { if v >= 0.0 { 1.0 } else { -1.0 } }
+
+    pub fn normal_to_int(norm: [f32; 3]) -> u32 {
+        let l1 = norm[0].abs() + norm[1].abs() + norm[2].abs();
+        let mut p = [norm[0] / l1, norm[1] / l1];
+        if norm[2] < 0.0 {
+            p = [
+                (1.0 - p[1].abs()) * sign_not_zero(p[0]),
+                (1.0 - p[0].abs()) * sign_not_zero(p[1]),
+            ];
+        }
+        (((65535.0 * ((p[0] + 1.0) / 2.0).clamp(0.0, 1.0)) as u32) << 16)
+            + (65535.0 * ((p[1] + 1.0) / 2.0).clamp(0.0, 1.0)) as u32
+    }
+
+    pub fn int_to_normal(num: u32) -> [f32; 3] {
+        let x = ((num >> 16) as f32 / 65535.0) * 2.0 - 1.0;
+        let y = ((num % (1 << 16)) as f32 / 65535.0) * 2.0 - 1.0;
+        let mut norm = [x, y, 1.0 - x.abs() - y.abs()];
+        if norm[2] < 0.0 {
+            norm = [
+                (1.0 - y.abs()) * sign_not_zero(x),
+                (1.0 - x.abs()) * sign_not_zero(y),
+                norm[2],
+            ];
+        }
+        let len = (norm[0] * norm[0] + norm[1] * norm[1] + norm[2] * norm[2])
+            .sqrt();
+        [norm[0] / len, norm[1] / len, norm[2] / len]
+    }
+}
+/* #endregion */
+
+#[cfg(test)]
+mod tests {
+    use super::cpu::*;
+
+    #[test]
+    fn octahedral_normals_round_trip() {
+        for i in 0..32 {
+            for j in 0..16 {
+                let theta = i as f32 / 32.0 * std::f32::consts::TAU;
+                let phi = j as f32 / 15.0 * std::f32::consts::PI;
+                let norm = [
+                    phi.sin() * theta.cos(),
+                    phi.sin() * theta.sin(),
+                    phi.cos(),
+                ];
+                let back = int_to_normal(normal_to_int(norm));
+                let dot = norm[0] * back[0]
+                    + norm[1] * back[1]
+                    + norm[2] * back[2];
+                assert!(dot > 0.9999, "{:?} came back as {:?}", norm, back);
+            }
+        }
+    }
+}
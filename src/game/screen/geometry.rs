@@ -0,0 +1,305 @@
+use wgpu::util::DeviceExt;
+use crate::constants;
+use crate::game::tile::Tile;
+use crate::game::tile::mapdata::meshes;
+use super::cull::{ CullStats, Hull, Projection };
+
+/// Hands out one mesh's slots in the transform buffer. Released slots are
+/// reused before the pool grows, so tiles streaming in and out don't
+/// allocate anything.
+pub struct SlotPool {
+    capacity: u32,
+    // every slot below this has been handed out at some point
+    high_water: u32,
+    free: Vec<u32>,
+}
+
+impl SlotPool {
+    pub fn new(capacity: u32) -> SlotPool {
+        Self {
+            capacity,
+            high_water: 0,
+            free: Vec::new(),
+        }
+    }
+
+    /// `None` once all `capacity` slots are live.
+    pub fn acquire(&mut self) -> Option<u32> {
+        if let Some(slot) = self.free.pop() {
+            return Some(slot);
+        }
+        if self.high_water == self.capacity {
+            return None;
+        }
+        self.high_water += 1;
+        Some(self.high_water - 1)
+    }
+
+    pub fn release(&mut self, slot: u32) {
+        debug_assert!(slot < self.high_water && !self.free.contains(&slot));
+        self.free.push(slot);
+    }
+
+    /// Slots the shaders have to look at, live or not.
+    pub fn high_water(&self) -> u32 { self.high_water }
+}
+
+/// Counters for the transform slots of every mesh together.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// held by a tile
+    pub live: u32,
+    /// released and waiting to be reused
+    pub pooled: u32,
+    /// slots the buffer has room for
+    pub capacity: u32,
+}
+
+/// Bytes in `MeshInstances`.
+const INSTANCES_SIZE: u64 = 16 + constants::MAX_TILES as u64 * 4;
+
+/// The per-mesh uniform saying which transforms to draw. Uniform arrays
+/// need a 16 byte stride, so the slots are packed four to a `vec4`.
+pub fn wgsl_instances() -> String {
+    format!("
+struct MeshInstances {{
+    // this mesh's transforms start at `first`, and the `count` tiles //
+    // on screen are in the slots listed in `slots`                   //
+    first: u32,
+    count: u32,
+    slots: array<vec4<u32>, {}>,
+}};
+", constants::MAX_TILES / 4)
+}
+
+/// One mesh's buffers, shared by every tile drawn with it.
+struct MeshBuffers {
+    index_count: u32,
+    hull: Hull,
+    // where this mesh's slots start in the transforms, and which of them
+    // are drawn this frame
+    instances: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pool: SlotPool,
+}
+
+/// Everything needed to draw one mesh's tiles this frame.
+pub struct MeshDraw<'a> {
+    pub bind_group: &'a wgpu::BindGroup,
+    pub index_count: u32,
+    pub instance_count: u32,
+}
+
+/// All the meshes in `mapdata`, uploaded once, and a single buffer with
+/// the transform of every tile being drawn. Each mesh owns `MAX_TILES`
+/// slots of it, and every tile holds one of its mesh's slots from
+/// `acquire` until `release`.
+pub struct Geometry {
+    meshes: Vec<MeshBuffers>,
+    transforms: wgpu::Buffer,
+}
+
+impl Geometry {
+    pub fn new(device: &wgpu::Device, layout: &wgpu::BindGroupLayout)
+        -> Geometry
+    {
+        let transforms = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Transform Buffer"),
+            size: (meshes().len() as u32 * constants::MAX_TILES) as u64
+                * 64, // mat4x4<f32>
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let meshes = meshes().iter().map(|mesh| {
+            let vbuf = device.create_buffer_init(
+                &wgpu::util::BufferInitDescriptor {
+                    label: Some("Vertex Buffer"),
+                    contents: &mesh.vertices,
+                    usage: wgpu::BufferUsages::STORAGE,
+                }
+            );
+            let ibuf = device.create_buffer_init(
+                &wgpu::util::BufferInitDescriptor {
+                    label: Some("Index Buffer"),
+                    contents: &mesh.indices,
+                    usage: wgpu::BufferUsages::STORAGE,
+                }
+            );
+            let mbuf = device.create_buffer_init(
+                &wgpu::util::BufferInitDescriptor {
+                    label: Some("Material Id Buffer"),
+                    contents: bytemuck::cast_slice(&mesh.materials),
+                    usage: wgpu::BufferUsages::STORAGE,
+                }
+            );
+            let instances = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Instance Buffer"),
+                size: INSTANCES_SIZE,
+                usage: wgpu::BufferUsages::UNIFORM
+                    | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            let bind_group = device.create_bind_group(
+                &wgpu::BindGroupDescriptor {
+                    label: Some("mesh_bind_group"),
+                    layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: vbuf.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: ibuf.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: transforms.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 3,
+                            resource: mbuf.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 4,
+                            resource: instances.as_entire_binding(),
+                        },
+                    ],
+                });
+
+            MeshBuffers {
+                index_count: mesh.index_count(),
+                hull: Hull::of_mesh(mesh),
+                instances,
+                bind_group,
+                pool: SlotPool::new(constants::MAX_TILES),
+            }
+        }).collect();
+
+        Self {
+            meshes,
+            transforms,
+        }
+    }
+
+    /// Gives the tile a slot for its transform, unless its mesh has no
+    /// free ones left, in which case it isn't drawn.
+    pub fn acquire(&mut self, tile: &mut Tile) {
+        debug_assert!(tile.get_slot().is_none());
+        let slot = self.meshes[tile.get_mesh()].pool.acquire();
+        if slot.is_none() {
+            log::warn!("out of slots for mesh {}", tile.get_mesh());
+        }
+        tile.set_slot(slot);
+    }
+
+    /// Hands the tile's slot back. Must happen before the tile is dropped,
+    /// which debug builds check.
+    pub fn release(&mut self, tile: &mut Tile) {
+        if let Some(slot) = tile.get_slot() {
+            self.meshes[tile.get_mesh()].pool.release(slot);
+            tile.set_slot(None);
+        }
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        let mut out = PoolStats::default();
+        for mesh in self.meshes.iter() {
+            out.pooled += mesh.pool.free.len() as u32;
+            out.live += mesh.pool.high_water - mesh.pool.free.len() as u32;
+            out.capacity += mesh.pool.capacity;
+        }
+        out
+    }
+
+    /// Writes every tile's transform into its slot, lists the slots of
+    /// the tiles that can land on screen, and returns the meshes that have
+    /// any of those to draw.
+    pub fn update(
+        &self,
+        queue: &wgpu::Queue,
+        tiles: &[Tile],
+        projection: &Projection,
+    ) -> (Vec<MeshDraw<'_>>, CullStats) {
+        let mut transforms = self.meshes.iter()
+            .map(|mesh| vec![0f32; mesh.pool.high_water() as usize * 16])
+            .collect::<Vec<_>>();
+        let mut visible = vec![Vec::new(); self.meshes.len()];
+        let mut stats = CullStats::default();
+        for tile in tiles.iter() {
+            if let Some(slot) = tile.get_slot() {
+                // the gpu only gets f32
+                let mat = tile.get_mat().cast::<f32>();
+                let start = slot as usize * 16;
+                transforms[tile.get_mesh()][start..start + 16]
+                    .copy_from_slice(mat.as_slice());
+
+                let hull = &self.meshes[tile.get_mesh()].hull;
+                if projection.visible(&mat, hull) {
+                    visible[tile.get_mesh()].push(slot);
+                    stats.drawn += 1;
+                } else {
+                    stats.culled += 1;
+                }
+            }
+        }
+
+        let mut out = Vec::new();
+        for (id, mesh) in self.meshes.iter().enumerate() {
+            let count = visible[id].len() as u32;
+            if count == 0 {
+                continue;
+            }
+
+            let first = id as u32 * constants::MAX_TILES;
+            queue.write_buffer(&self.transforms, first as u64 * 64,
+                bytemuck::cast_slice(&transforms[id]));
+            queue.write_buffer(&mesh.instances, 0,
+                bytemuck::cast_slice(&[first, count, 0, 0]));
+            queue.write_buffer(&mesh.instances, 16,
+                bytemuck::cast_slice(&visible[id]));
+            out.push(MeshDraw {
+                bind_group: &mesh.bind_group,
+                index_count: mesh.index_count,
+                instance_count: count,
+            });
+        }
+        (out, stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn released_slots_are_reused_first() {
+        let mut pool = SlotPool::new(4);
+        let a = pool.acquire().unwrap();
+        let b = pool.acquire().unwrap();
+        pool.release(a);
+        assert_eq!(pool.acquire(), Some(a));
+        assert_eq!(pool.high_water(), 2);
+
+        pool.release(b);
+        pool.release(a);
+        for _ in 0..2 {
+            pool.acquire().unwrap();
+        }
+        assert_eq!(pool.high_water(), 2);
+    }
+
+    #[test]
+    fn runs_out_at_capacity() {
+        let mut pool = SlotPool::new(3);
+        let slots = (0..3).map(|_| pool.acquire().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(slots, [0, 1, 2]);
+        assert_eq!(pool.acquire(), None);
+
+        pool.release(1);
+        assert_eq!(pool.acquire(), Some(1));
+    }
+}
@@ -0,0 +1,270 @@
+use super::{ gbuffer, shaders };
+use super::geometry::MeshDraw;
+use super::shaders::PcBinding;
+
+/// The other way of filling the G-buffer: a regular render pipeline draws
+/// every channel into its own target with a real depth test, and a compute
+/// pass packs the targets into the same buffer the compute backend writes.
+pub struct HardwareRaster {
+    size: (u32, u32),
+    targets: Targets,
+    pipeline: wgpu::RenderPipeline,
+
+    pack_bind_group_layout: wgpu::BindGroupLayout,
+    pack_pipeline: wgpu::ComputePipeline,
+
+    pc: PcBinding,
+}
+
+impl HardwareRaster {
+    pub fn new(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        vertex_bind_group_layout: &wgpu::BindGroupLayout,
+        material_bind_group_layout: &wgpu::BindGroupLayout,
+        g_buffer_bind_group_layout: &wgpu::BindGroupLayout,
+        size: (u32, u32),
+        pc: PcBinding,
+    ) -> HardwareRaster {
+        /* #region RENDER PIPELINE SETUP */
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: None,
+            source: wgpu::ShaderSource::Wgsl(shaders::hardware(pc).into()),
+        });
+        let pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("hardware"),
+                bind_group_layouts: &[
+                    camera_bind_group_layout,
+                    vertex_bind_group_layout,
+                    material_bind_group_layout,
+                ],
+                push_constant_ranges:
+                    &pc.ranges(wgpu::ShaderStages::VERTEX_FRAGMENT),
+            });
+        let target_states = gbuffer::LAYOUT.iter().map(|_|
+            Some(wgpu::ColorTargetState {
+                format: gbuffer::TARGET_FORMAT,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            })
+        ).collect::<Vec<_>>();
+        let pipeline = device.create_render_pipeline(
+            &wgpu::RenderPipelineDescriptor {
+                label: Some("hardware"),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                // backfaces are dropped in `vs_main`, the same way the
+                // compute backend does it
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: wgpu::TextureFormat::Depth32Float,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::Less,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState::default(),
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: "fs_main",
+                    targets: &target_states,
+                }),
+                multiview: None,
+            });
+        /* #endregion */
+
+        /* #region PACK PIPELINE SETUP */
+        let pack_bind_group_layout_entries = (0..gbuffer::LAYOUT.len())
+            .map(|i| wgpu::BindGroupLayoutEntry {
+                binding: i as u32,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Uint,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            }).collect::<Vec<_>>();
+        let pack_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &pack_bind_group_layout_entries,
+            });
+        let pack_shader = device.create_shader_module(
+            wgpu::ShaderModuleDescriptor {
+                label: None,
+                source: wgpu::ShaderSource::Wgsl(shaders::pack(pc).into()),
+            });
+        let pack_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("pack"),
+                bind_group_layouts: &[
+                    g_buffer_bind_group_layout,
+                    &pack_bind_group_layout,
+                    camera_bind_group_layout,
+                ],
+                push_constant_ranges: &pc.ranges(wgpu::ShaderStages::COMPUTE),
+            });
+        let pack_pipeline =
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: None,
+                layout: Some(&pack_pipeline_layout),
+                module: &pack_shader,
+                entry_point: "pack_main",
+            });
+        /* #endregion */
+
+        let targets = Targets::new(device, &pack_bind_group_layout, size);
+
+        Self {
+            size,
+            targets,
+            pipeline,
+
+            pack_bind_group_layout,
+            pack_pipeline,
+
+            pc,
+        }
+    }
+
+    /// Remakes the render targets at a new internal resolution.
+    pub fn resize(&mut self, device: &wgpu::Device, size: (u32, u32)) {
+        self.size = size;
+        self.targets =
+            Targets::new(device, &self.pack_bind_group_layout, size);
+    }
+
+    pub fn draw(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        draws: &[MeshDraw],
+        camera_bind_group: &wgpu::BindGroup,
+        material_bind_group: &wgpu::BindGroup,
+        g_buffer_bind_group: &wgpu::BindGroup,
+        pc_bytes: &[u8],
+    ) {
+        // cleared to zero, which is what empty pixels hold in every channel
+        let color_attachments = self.targets.views.iter().map(|view|
+            Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: true,
+                },
+            })
+        ).collect::<Vec<_>>();
+
+        {
+            let mut rpass = encoder.begin_render_pass(
+                &wgpu::RenderPassDescriptor {
+                    label: Some("hardware"),
+                    color_attachments: &color_attachments,
+                    depth_stencil_attachment: Some(
+                        wgpu::RenderPassDepthStencilAttachment {
+                            view: &self.targets.depth,
+                            depth_ops: Some(wgpu::Operations {
+                                load: wgpu::LoadOp::Clear(1.0),
+                                store: false,
+                            }),
+                            stencil_ops: None,
+                        }),
+                });
+            rpass.set_pipeline(&self.pipeline);
+            if self.pc == PcBinding::Push {
+                rpass.set_push_constants(
+                    wgpu::ShaderStages::VERTEX_FRAGMENT, 0, pc_bytes);
+            }
+            rpass.set_bind_group(0, camera_bind_group, &[]);
+            rpass.set_bind_group(2, material_bind_group, &[]);
+
+            for draw in draws.iter() {
+                rpass.set_bind_group(1, draw.bind_group, &[]);
+                rpass.draw(0..draw.index_count, 0..draw.instance_count);
+            }
+        }
+
+        {
+            let mut cpass = encoder.begin_compute_pass(
+                &wgpu::ComputePassDescriptor {
+                    label: Some("pack"),
+                });
+            cpass.set_pipeline(&self.pack_pipeline);
+            if self.pc == PcBinding::Push {
+                cpass.set_push_constants(0, pc_bytes);
+            }
+            cpass.set_bind_group(0, g_buffer_bind_group, &[]);
+            cpass.set_bind_group(1, &self.targets.pack_bind_group, &[]);
+            cpass.set_bind_group(2, camera_bind_group, &[]);
+            cpass.dispatch_workgroups(
+                self.size.0.div_ceil(16),
+                self.size.1.div_ceil(16),
+                1
+            );
+        }
+    }
+}
+
+/// The targets each channel is drawn into, sized by the internal
+/// resolution.
+struct Targets {
+    views: Vec<wgpu::TextureView>,
+    depth: wgpu::TextureView,
+    pack_bind_group: wgpu::BindGroup,
+}
+
+impl Targets {
+    fn new(
+        device: &wgpu::Device,
+        pack_bind_group_layout: &wgpu::BindGroupLayout,
+        size: (u32, u32),
+    ) -> Targets {
+        let size = wgpu::Extent3d {
+            width: size.0,
+            height: size.1,
+            depth_or_array_layers: 1,
+        };
+
+        let views = gbuffer::LAYOUT.iter().map(|channel|
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(channel.name),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: gbuffer::TARGET_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+            }).create_view(&wgpu::TextureViewDescriptor::default())
+        ).collect::<Vec<_>>();
+        let depth = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("hardware depth"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Depth32Float,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        }).create_view(&wgpu::TextureViewDescriptor::default());
+
+        let pack_bind_group_entries = views.iter().enumerate()
+            .map(|(i, view)| wgpu::BindGroupEntry {
+                binding: i as u32,
+                resource: wgpu::BindingResource::TextureView(view),
+            }).collect::<Vec<_>>();
+        let pack_bind_group = device.create_bind_group(
+            &wgpu::BindGroupDescriptor {
+                label: Some("hardware targets"),
+                layout: pack_bind_group_layout,
+                entries: &pack_bind_group_entries,
+            });
+
+        Self { views, depth, pack_bind_group }
+    }
+}
@@ -0,0 +1,312 @@
+use nalgebra::{ Matrix4, Vector4 };
+use crate::constants;
+use crate::game::isometry;
+use crate::game::tile::{ self, Tile };
+use super::renderer::Vertex;
+use super::shaders;
+
+/// A corner of something on the minimap, in the Poincaré disk around the
+/// player until `place` moves it onto the screen.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MapVertex {
+    pub position: [f32; 2],
+    pub color: [f32; 4],
+}
+
+// straight pieces each side of a tile and the rim are drawn with
+const EDGE_SEGMENTS: usize = 8;
+const RIM_SEGMENTS: usize = 64;
+
+const RIM: [f32; 4] = [1.0, 1.0, 1.0, 0.8];
+const OUTLINE: [f32; 4] = [1.0, 1.0, 1.0, 0.35];
+const HEADING: [f32; 4] = [1.0, 0.9, 0.3, 1.0];
+const BACKDROP: [f32; 4] = [0.0, 0.0, 0.0, 0.6];
+
+/// Where the point `a` on the hyperboloid, in the player's coordinates,
+/// lands in the Poincaré disk. Heights are dropped.
+fn to_disk(a: Vector4<f64>) -> [f32; 2] {
+    [a.x / (1.0 + a.w), a.y / (1.0 + a.w)].map(|x| x as f32)
+}
+
+/// The point `t` of the way along the geodesic from `a` to `b`, though not
+/// `t` of the distance. Geodesics lie in planes through the origin, so
+/// the chord between them only has to be pushed back onto the hyperboloid.
+fn geodesic(a: Vector4<f64>, b: Vector4<f64>, t: f64) -> Vector4<f64> {
+    let p = a * (1.0 - t) + b * t;
+    p / (p.w * p.w - p.x * p.x - p.y * p.y).sqrt()
+}
+
+fn circle(center: [f32; 2], radius: f32, i: usize, n: usize) -> [f32; 2] {
+    let (sin, cos) = (i as f32 / n as f32 * std::f32::consts::TAU).sin_cos();
+    [center[0] + radius * cos, center[1] + radius * sin]
+}
+
+/// The dark disk the minimap is drawn on, as triangles.
+fn backdrop() -> Vec<MapVertex> {
+    (0..RIM_SEGMENTS).flat_map(|i| [
+        [0.0, 0.0],
+        circle([0.0; 2], 1.0, i, RIM_SEGMENTS),
+        circle([0.0; 2], 1.0, i + 1, RIM_SEGMENTS),
+    ]).map(|position| MapVertex { position, color: BACKDROP }).collect()
+}
+
+/// Everything on the minimap, as pairs of line ends: the disk's rim, the
+/// outline of every tile in `tiles`, a marker for each of `points` in its
+/// own color, and an arrow for the player. The disk is centred on the
+/// player, whose `frame` is given, heading up it. The camera trailing
+/// behind and looking ahead doesn't move it.
+fn lines(player: &Matrix4<f64>, tiles: &[Tile], points: &[Vertex])
+    -> Vec<MapVertex>
+{
+    let mut out = Vec::new();
+    let mut line = |a: [f32; 2], b: [f32; 2], color: [f32; 4]| {
+        out.push(MapVertex { position: a, color });
+        out.push(MapVertex { position: b, color });
+    };
+
+    for i in 0..RIM_SEGMENTS {
+        line(
+            circle([0.0; 2], 1.0, i, RIM_SEGMENTS),
+            circle([0.0; 2], 1.0, i + 1, RIM_SEGMENTS),
+            RIM,
+        );
+    }
+
+    let world_to_player = isometry::inverse(player);
+    for tile in tiles {
+        let to_player = world_to_player * tile.get_mat();
+        let corners = tile::CORNERS.map(|c| to_player * Vector4::from(c));
+        for i in 0..4 {
+            let (a, b) = (corners[i], corners[(i + 1) % 4]);
+            for j in 0..EDGE_SEGMENTS {
+                let t = j as f64 / EDGE_SEGMENTS as f64;
+                line(
+                    to_disk(geodesic(a, b, t)),
+                    to_disk(geodesic(a, b, t + 1.0 / EDGE_SEGMENTS as f64)),
+                    OUTLINE,
+                );
+            }
+        }
+    }
+
+    for point in points {
+        let p = to_disk(
+            world_to_player * Vector4::from(point.position.map(f64::from)));
+        let [r, g, b, _] = point.color;
+        for i in 0..4 {
+            line(
+                circle(p, 0.04, i, 4),
+                circle(p, 0.04, i + 1, 4),
+                [r, g, b, 1.0],
+            );
+        }
+    }
+
+    let arrow = [[0.0, 0.08], [0.05, -0.06], [0.0, -0.03], [-0.05, -0.06]];
+    for i in 0..4 {
+        line(arrow[i], arrow[(i + 1) % 4], HEADING);
+    }
+
+    out
+}
+
+/// Moves `p` from the disk to where the minimap goes, in the top right of
+/// the image, in clip space. `borders` and `size` are what the frame is
+/// drawn with.
+pub fn place(p: [f32; 2], borders: &[f32; 2], size: (u32, u32)) -> [f32; 2] {
+    // in the image's own clip space first, then squashed by the borders
+    let radius = [
+        constants::MINIMAP_SIZE * size.1 as f32 / size.0 as f32,
+        constants::MINIMAP_SIZE,
+    ];
+    let margin = 0.05;
+    [0, 1].map(|i| {
+        let center = 1.0 - radius[i] * (1.0 + margin / radius[1]);
+        (center + p[i] * radius[i]) * (1.0 - borders[i])
+    })
+}
+
+/// The backdrop and then `lines`, moved onto the screen by `place`. Anything
+/// past `constants::MINIMAP_VERTICES` is left off.
+pub fn vertices(
+    player: &Matrix4<f64>,
+    tiles: &[Tile],
+    points: &[Vertex],
+    borders: &[f32; 2],
+    size: (u32, u32),
+) -> Vec<MapVertex> {
+    let mut out = backdrop();
+    out.extend(lines(player, tiles, points));
+    // line ends come in pairs after the backdrop's even count
+    out.truncate(constants::MINIMAP_VERTICES as usize & !1);
+    for v in out.iter_mut() {
+        v.position = place(v.position, borders, size);
+    }
+    out
+}
+
+/// Draws the minimap over a finished frame.
+pub struct Minimap {
+    fill_pipeline: wgpu::RenderPipeline,
+    line_pipeline: wgpu::RenderPipeline,
+    vertex_buffer: wgpu::Buffer,
+    // how many of `vertex_buffer`'s first vertices are the backdrop
+    backdrop: u32,
+    pub shown: bool,
+}
+
+impl Minimap {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat)
+        -> Minimap
+    {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: None,
+            source: wgpu::ShaderSource::Wgsl(shaders::minimap().into()),
+        });
+        let layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("minimap"),
+                bind_group_layouts: &[],
+                push_constant_ranges: &[],
+            });
+        let attributes = wgpu::vertex_attr_array![
+            0 => Float32x2,
+            1 => Float32x4,
+        ];
+        let pipeline = |topology| device.create_render_pipeline(
+            &wgpu::RenderPipelineDescriptor {
+                label: Some("minimap"),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[wgpu::VertexBufferLayout {
+                        array_stride: std::mem::size_of::<MapVertex>()
+                            as wgpu::BufferAddress,
+                        step_mode: wgpu::VertexStepMode::Vertex,
+                        attributes: &attributes,
+                    }],
+                },
+                primitive: wgpu::PrimitiveState {
+                    topology,
+                    ..Default::default()
+                },
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                multiview: None,
+            });
+
+        let vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("minimap"),
+            size: (constants::MINIMAP_VERTICES as usize
+                * std::mem::size_of::<MapVertex>()) as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            fill_pipeline: pipeline(wgpu::PrimitiveTopology::TriangleList),
+            line_pipeline: pipeline(wgpu::PrimitiveTopology::LineList),
+            vertex_buffer,
+            backdrop: backdrop().len() as u32,
+            shown: true,
+        }
+    }
+
+    /// Uploads `vertices`, from `vertices()`, and draws them in `rpass`.
+    pub fn draw<'a>(
+        &'a self,
+        queue: &wgpu::Queue,
+        rpass: &mut wgpu::RenderPass<'a>,
+        vertices: &[MapVertex],
+    ) {
+        queue.write_buffer(
+            &self.vertex_buffer, 0, bytemuck::cast_slice(vertices));
+
+        rpass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        rpass.set_pipeline(&self.fill_pipeline);
+        rpass.draw(0..self.backdrop, 0..1);
+        rpass.set_pipeline(&self.line_pipeline);
+        rpass.draw(self.backdrop..vertices.len() as u32, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::tile::permutation::IDENTITY;
+
+    use crate::game::isometry::Motion;
+    use crate::game::player::Player;
+
+    /// Where the world point `p` ends up on the minimap of `player`.
+    fn marker(player: &Player, p: [f64; 4]) -> [f32; 2] {
+        let point = Vertex {
+            position: p.map(|x| x as f32),
+            color: [1.0; 4],
+        };
+        let lines = lines(player.frame(), &[], &[point]);
+        // the marker is a diamond around it, so its corners average to it
+        let corners = &lines[2 * RIM_SEGMENTS..2 * RIM_SEGMENTS + 8];
+        [0, 1].map(|i| corners.iter().map(|v| v.position[i]).sum::<f32>()
+            / corners.len() as f32)
+    }
+
+    #[test]
+    fn player_heads_up_the_middle_of_the_disk() {
+        let mut player = Player::new();
+        player.walk(0.3, -0.4);
+        player.turn(0.8);
+        let p = marker(&player, player.get_position());
+        assert!(p[0].hypot(p[1]) < 1e-5, "{:?}", p);
+
+        let ahead = player.frame() * isometry::exp(Motion {
+            boost: [0.0, 1.0],
+            turn: 0.0,
+        });
+        let p = marker(&player, [0, 1, 2, 3].map(|i| ahead[(i, 3)]));
+        assert!(p[0].abs() < 1e-5, "{:?}", p);
+        assert!((p[1] - (0.5f32).tanh()).abs() < 1e-5, "{:?}", p);
+    }
+
+    #[test]
+    fn tile_outlines_stay_in_the_disk() {
+        let tiles = [Tile::new(IDENTITY)];
+        let lines = lines(&Matrix4::identity(), &tiles, &[]);
+        assert_eq!(lines.len(), 2 * (RIM_SEGMENTS + 4 * EDGE_SEGMENTS + 4));
+        for v in lines.iter() {
+            let [x, y] = v.position;
+            assert!(x * x + y * y <= 1.0 + 1e-5, "{:?}", v);
+        }
+        // the corners are acosh(√3) from the middle of the tile
+        let corner = (1.732_050_8f32.acosh() / 2.0).tanh();
+        let r = lines[2 * RIM_SEGMENTS].position;
+        assert!(((r[0] * r[0] + r[1] * r[1]).sqrt() - corner).abs() < 1e-5);
+    }
+
+    #[test]
+    fn minimap_is_round_in_the_top_right() {
+        // a 1000x360 window showing 640x360 pixels, fit to its height
+        let borders = [0.36, 0.0];
+        let size = (640, 360);
+        let right = place([1.0, 0.0], &borders, size);
+        let top = place([0.0, 1.0], &borders, size);
+        let center = place([0.0, 0.0], &borders, size);
+        assert!(center[0] > 0.0 && center[1] > 0.0);
+        assert!(right[0] < 1.0 - borders[0] && top[1] < 1.0);
+        // in window pixels, half a clip space unit is 500 across, 180 up
+        let across = (right[0] - center[0]) * 500.0;
+        let up = (top[1] - center[1]) * 180.0;
+        assert!((across - up).abs() < 1e-3, "{} {}", across, up);
+    }
+}
@@ -0,0 +1,150 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+/// What headless screens render in. It's already the byte order pngs use.
+pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+fn extent(size: (u32, u32)) -> wgpu::Extent3d {
+    wgpu::Extent3d {
+        width: size.0,
+        height: size.1,
+        depth_or_array_layers: 1,
+    }
+}
+
+/// A texture the size of the screen to render a frame into, instead of a
+/// window's surface, and read it back.
+pub struct Target {
+    texture: wgpu::Texture,
+    format: wgpu::TextureFormat,
+    size: (u32, u32),
+}
+
+impl Target {
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        size: (u32, u32),
+    ) -> Target {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("offscreen"),
+            size: extent(size),
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::COPY_SRC,
+        });
+        Self { texture, format, size }
+    }
+
+    pub fn view(&self) -> wgpu::TextureView {
+        self.texture.create_view(&wgpu::TextureViewDescriptor::default())
+    }
+
+    /// Copies the texture back and waits for it. Comes out as rows of
+    /// rgba bytes, top to bottom.
+    pub fn read_rgba(&self, device: &wgpu::Device, queue: &wgpu::Queue)
+        -> Vec<u8>
+    {
+        let row_bytes = padded_row_bytes(self.size.0);
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("offscreen readback"),
+            size: (row_bytes * self.size.1) as u64,
+            usage: wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(
+            &wgpu::CommandEncoderDescriptor { label: Some("readback") });
+        encoder.copy_texture_to_buffer(
+            self.texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(row_bytes),
+                    rows_per_image: None,
+                },
+            },
+            extent(self.size),
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |result|
+            result.expect("couldn't map the readback buffer"));
+        device.poll(wgpu::Maintain::Wait);
+        let bgra = matches!(self.format,
+            wgpu::TextureFormat::Bgra8Unorm
+            | wgpu::TextureFormat::Bgra8UnormSrgb);
+        let out = unpad(&slice.get_mapped_range(), self.size.0, bgra);
+        buffer.unmap();
+        out
+    }
+}
+
+/// Rows in a texture copy have to start 256 bytes apart.
+fn padded_row_bytes(width: u32) -> u32 {
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    (width * 4).div_ceil(align) * align
+}
+
+/// Drops the padding off the end of each row, and puts blue and red back
+/// where they go if the texture had them swapped.
+fn unpad(data: &[u8], width: u32, bgra: bool) -> Vec<u8> {
+    let row_bytes = padded_row_bytes(width) as usize;
+    let mut out = Vec::with_capacity(data.len());
+    for row in data.chunks_exact(row_bytes) {
+        for pixel in row[..width as usize * 4].chunks_exact(4) {
+            if bgra {
+                out.extend([pixel[2], pixel[1], pixel[0], pixel[3]]);
+            } else {
+                out.extend_from_slice(pixel);
+            }
+        }
+    }
+    out
+}
+
+/// Writes `size` pixels worth of rgba bytes to `path`.
+pub fn save_png(path: &Path, size: (u32, u32), rgba: &[u8])
+    -> Result<(), png::EncodingError>
+{
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, size.0, size.1);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+    encoder.write_header()?.write_image_data(rgba)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rows_are_unpadded_and_swizzled() {
+        let width = 3;
+        let row_bytes = padded_row_bytes(width) as usize;
+        assert_eq!(row_bytes, 256);
+
+        let mut data = vec![0xee; row_bytes * 2];
+        for (y, row) in data.chunks_exact_mut(row_bytes).enumerate() {
+            for x in 0..width as usize {
+                let v = (y * 10 + x) as u8;
+                row[4 * x..4 * x + 4].copy_from_slice(&[v, 1, 2, 3]);
+            }
+        }
+
+        let rgba = unpad(&data, width, false);
+        assert_eq!(rgba.len(), 2 * 3 * 4);
+        assert_eq!(&rgba[12..16], &[10, 1, 2, 3]);
+
+        let swapped = unpad(&data, width, true);
+        assert_eq!(&swapped[12..16], &[2, 1, 10, 3]);
+    }
+}
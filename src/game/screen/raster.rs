@@ -0,0 +1,568 @@
+use crate::constants;
+
+/// Screen bins across and down at `size`, for sizing the binning buffers.
+pub fn bin_grid(size: (u32, u32)) -> (u32, u32) {
+    (
+        size.0.div_ceil(constants::BIN_SIZE),
+        size.1.div_ceil(constants::BIN_SIZE),
+    )
+}
+
+/// Bytes per `ScreenTriangle`, keep in step with `WGSL_RASTER`.
+pub const SCREEN_TRIANGLE_SIZE: u64 = 80;
+
+/// The triangle counter, followed by one counter per bin.
+pub fn counters_size(size: (u32, u32)) -> u64 {
+    let bins = bin_grid(size);
+    (1 + bins.0 as u64 * bins.1 as u64) * 4
+}
+
+pub const SCREEN_TRIANGLES_SIZE: u64 =
+    constants::MAX_SCREEN_TRIANGLES as u64 * SCREEN_TRIANGLE_SIZE;
+
+/// What binning got through in a frame, and what it had to leave out,
+/// worked out from the counters after it. The counters keep going past
+/// what's stored, so anything over is what was dropped.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BinStats {
+    /// triangles binned, including any there was no room for
+    pub triangles: u32,
+    /// the most triangles any one bin was handed
+    pub fullest_bin: u32,
+    /// triangles past `MAX_SCREEN_TRIANGLES`, drawn nowhere
+    pub dropped_triangles: u32,
+    /// bin entries past `BIN_CAPACITY`, each a hole in that bin
+    pub dropped_entries: u32,
+}
+
+impl BinStats {
+    /// Reads the counters buffer, laid out as `counters_size` has it.
+    pub fn from_counters(counters: &[u32]) -> BinStats {
+        let (triangles, bins) = counters.split_first().unwrap_or((&0, &[]));
+        BinStats {
+            triangles: *triangles,
+            fullest_bin: bins.iter().copied().max().unwrap_or(0),
+            dropped_triangles:
+                triangles.saturating_sub(constants::MAX_SCREEN_TRIANGLES),
+            dropped_entries: bins.iter()
+                .map(|n| n.saturating_sub(constants::BIN_CAPACITY))
+                .sum(),
+        }
+    }
+
+    /// Whether anything was left out of the frame.
+    pub fn overflowed(&self) -> bool {
+        self.dropped_triangles > 0 || self.dropped_entries > 0
+    }
+}
+
+pub fn bin_tris_size(size: (u32, u32)) -> u64 {
+    let bins = bin_grid(size);
+    bins.0 as u64 * bins.1 as u64 * constants::BIN_CAPACITY as u64 * 4
+}
+
+/// Constants, the binned triangle format and the fixed-point edge
+/// functions, prepended to both `binning.wgsl` and `g_buffer.wgsl`.
+pub fn wgsl_prelude() -> String {
+    format!(
+        "let SUBPIXEL_SCALE: i32 = {};\n\
+        let MAX_TRIANGLE_EXTENT: i32 = {};\n\
+        let GUARD_BAND: u32 = {}u;\n\
+        let BIN_SIZE: u32 = {}u;\n\
+        let BIN_CAPACITY: u32 = {}u;\n\
+        let MAX_SCREEN_TRIANGLES: u32 = {}u;\n{}",
+        1 << constants::SUBPIXEL_BITS,
+        constants::MAX_TRIANGLE_EXTENT << constants::SUBPIXEL_BITS,
+        constants::GUARD_BAND,
+        constants::BIN_SIZE,
+        constants::BIN_CAPACITY,
+        constants::MAX_SCREEN_TRIANGLES,
+        WGSL_RASTER,
+    )
+}
+
+const WGSL_RASTER: &str = "
+struct ScreenTriangle {
+    // snapped and wound counter-clockwise //
+    a: vec2<i32>,
+    b: vec2<i32>,
+    c: vec2<i32>,
+    area: i32,
+    normal: u32,
+    z: vec4<f32>,
+    // a, b, c and emissive //
+    colors: vec4<u32>,
+    material: u32,
+};
+
+struct ScreenTriangles {
+    values: array<ScreenTriangle>
+};
+
+struct Counters {
+    values: array<atomic<u32>>
+};
+
+fn bins_x() -> u32 {
+    return (u32(pc.pix_x) + BIN_SIZE - 1u) / BIN_SIZE;
+}
+
+fn snap(v: vec3<f32>) -> vec2<i32> {
+    // rounds a screen position to the fixed-point subpixel grid //
+    return vec2<i32>(floor(v.xy * f32(SUBPIXEL_SCALE) + 0.5));
+}
+
+fn edge(a: vec2<i32>, b: vec2<i32>, p: vec2<i32>) -> i32 {
+    // twice the signed area of a, b, p //
+    return (b.x - a.x) * (p.y - a.y) - (b.y - a.y) * (p.x - a.x);
+}
+
+fn fill_bias(a: vec2<i32>, b: vec2<i32>) -> i32 {
+    // pixel centers exactly on an edge only count for top and left //
+    // edges, so neighbouring triangles never both draw them         //
+    let dx = a.y - b.y;
+    let dy = b.x - a.x;
+    if dx > 0 || (dx == 0 && dy > 0) {
+        return 0;
+    }
+    return 1;
+}
+";
+
+/* #region CPU REFERENCE */
+// Mirrors the coverage side of `bin_main` and `raster_main`: vertices snap
+// to a fixed-point grid, pixels are sampled at their centers, and pixel
+// centers landing exactly on an edge belong to the triangle only if it's
+// a top or left edge.
+
+#[cfg(test)]
+pub mod cpu {
+    use crate::constants;
+    use super::bin_grid;
+
+    const SCALE: i32 = 1 << constants::SUBPIXEL_BITS;
+
+    pub fn snap(v: [f32; 2]) -> [i32; 2] {
+        [
+            (v[0] * SCALE as f32 + 0.5).floor() as i32,
+            (v[1] * SCALE as f32 + 0.5).floor() as i32,
+        ]
+    }
+
+    pub fn edge(a: [i32; 2], b: [i32; 2], p: [i32; 2]) -> i32 {
+        (b[0] - a[0]) * (p[1] - a[1]) - (b[1] - a[1]) * (p[0] - a[0])
+    }
+
+    pub fn fill_bias(a: [i32; 2], b: [i32; 2]) -> i32 {
+        let dx = a[1] - b[1];
+        let dy = b[0] - a[0];
+        if dx > 0 || (dx == 0 && dy > 0) { 0 } else { 1 }
+    }
+
+    /// Mirrors the `clip_plane` calls in `bin_main`, cutting the polygon down
+    /// to the guard band and the depth range. Corners start with screen x, y
+    /// and z, and anything after that is interpolated along with them.
+    pub fn clip<const N: usize>(poly: &[[f32; N]], size: (u32, u32))
+        -> Vec<[f32; N]>
+    {
+        let guard = constants::GUARD_BAND as f32;
+        let mut poly = poly.to_vec();
+        for (axis, side, bound) in [
+            (0, 1.0, -guard),
+            (0, -1.0, size.0 as f32 + guard),
+            (1, 1.0, -guard),
+            (1, -1.0, size.1 as f32 + guard),
+            (2, 1.0, constants::FAR_PLANE),
+            (2, -1.0, constants::NEAR_PLANE),
+        ] {
+            poly = clip_plane(&poly, axis, side, bound);
+        }
+        poly
+    }
+
+    /// `clip_plane` in binning.wgsl: the part of `poly` where
+    /// `side * (corner[axis] - bound) >= 0`.
+    pub fn clip_plane<const N: usize>(
+        poly: &[[f32; N]],
+        axis: usize,
+        side: f32,
+        bound: f32,
+    ) -> Vec<[f32; N]> {
+        let mut out = Vec::new();
+        for (i, &cur) in poly.iter().enumerate() {
+            let next = poly[(i + 1) % poly.len()];
+            let d_cur = side * (cur[axis] - bound);
+            let d_next = side * (next[axis] - bound);
+            if d_cur >= 0.0 {
+                out.push(cur);
+            }
+            if (d_cur >= 0.0) != (d_next >= 0.0) {
+                let ((inside, d_in), (outside, d_out)) = if d_cur >= 0.0 {
+                    ((cur, d_cur), (next, d_next))
+                } else {
+                    ((next, d_next), (cur, d_cur))
+                };
+                let t = d_in / (d_in - d_out);
+                out.push(std::array::from_fn(
+                    |j| inside[j] * (1.0 - t) + outside[j] * t));
+            }
+        }
+        out
+    }
+
+    /// First and last pixel centers inside the snapped triangle's bounding
+    /// box, or `None` if it's too big or entirely off screen.
+    pub fn pixel_bounds(
+        sa: [i32; 2],
+        sb: [i32; 2],
+        sc: [i32; 2],
+        size: (u32, u32),
+    ) -> Option<([i32; 2], [i32; 2])> {
+        let min = [0, 1].map(|i| sa[i].min(sb[i]).min(sc[i]));
+        let max = [0, 1].map(|i| sa[i].max(sb[i]).max(sc[i]));
+        let limit = (constants::MAX_TRIANGLE_EXTENT << constants::SUBPIXEL_BITS)
+            as i32;
+        if max[0] - min[0] > limit || max[1] - min[1] > limit {
+            return None;
+        }
+
+        let half = SCALE / 2;
+        let start = [0, 1].map(|i| ((min[i] - half) as f32 / SCALE as f32)
+            .ceil().max(0.0) as i32);
+        let end = [
+            (((max[0] - half) as f32 / SCALE as f32).floor() as i32)
+                .min(size.0 as i32 - 1),
+            (((max[1] - half) as f32 / SCALE as f32).floor() as i32)
+                .min(size.1 as i32 - 1),
+        ];
+        if start[0] > end[0] || start[1] > end[1] {
+            return None;
+        }
+        Some((start, end))
+    }
+
+    /// Bins `bin_main` adds the triangle to.
+    pub fn bins(a: [f32; 2], b: [f32; 2], c: [f32; 2], size: (u32, u32))
+        -> Vec<[u32; 2]>
+    {
+        let Some((start, end)) = pixel_bounds(snap(a), snap(b), snap(c), size)
+        else {
+            return Vec::new();
+        };
+        let first = start.map(|s| s as u32 / constants::BIN_SIZE);
+        let last = end.map(|e| e as u32 / constants::BIN_SIZE);
+        let mut out = Vec::new();
+        for by in first[1]..=last[1] {
+            for bx in first[0]..=last[0] {
+                out.push([bx, by]);
+            }
+        }
+        out
+    }
+
+    /// Mirrors the counting in `bin_triangle`, binning `triangles` one after
+    /// another: the counters after them all, and the triangles each bin's list
+    /// ends up with, which is all `raster_main` sees.
+    pub fn bin_all(triangles: &[[[f32; 2]; 3]], size: (u32, u32))
+        -> (Vec<u32>, Vec<Vec<u32>>)
+    {
+        let grid = bin_grid(size);
+        let mut counters = vec![0; 1 + (grid.0 * grid.1) as usize];
+        let mut lists = vec![Vec::new(); (grid.0 * grid.1) as usize];
+        for &[a, b, c] in triangles {
+            let bins = bins(a, b, c, size);
+            if edge(snap(a), snap(b), snap(c)) == 0 || bins.is_empty() {
+                continue;
+            }
+            let slot = counters[0];
+            counters[0] += 1;
+            if slot >= constants::MAX_SCREEN_TRIANGLES {
+                continue;
+            }
+            for [bx, by] in bins {
+                let bin = (bx + by * grid.0) as usize;
+                if counters[1 + bin] < constants::BIN_CAPACITY {
+                    lists[bin].push(slot);
+                }
+                counters[1 + bin] += 1;
+            }
+        }
+        (counters, lists)
+    }
+
+    /// Pixels covered by the triangle, with their barycentric coordinates.
+    pub fn rasterize(
+        a: [f32; 2],
+        b: [f32; 2],
+        c: [f32; 2],
+        size: (u32, u32),
+    ) -> Vec<([u32; 2], [f32; 3])> {
+        let mut out = Vec::new();
+        let (sa, mut sb, mut sc) = (snap(a), snap(b), snap(c));
+        let mut area = edge(sa, sb, sc);
+        if area == 0 {
+            return out;
+        }
+        let flipped = area < 0;
+        if flipped {
+            std::mem::swap(&mut sb, &mut sc);
+            area = -area;
+        }
+
+        let Some((start, end)) = pixel_bounds(sa, sb, sc, size) else {
+            return out;
+        };
+
+        let half = SCALE / 2;
+        let bias = [fill_bias(sb, sc), fill_bias(sc, sa), fill_bias(sa, sb)];
+        for y in start[1]..=end[1] {
+            for x in start[0]..=end[0] {
+                let p = [x * SCALE + half, y * SCALE + half];
+                let w = [edge(sb, sc, p), edge(sc, sa, p), edge(sa, sb, p)];
+                if (0..3).any(|i| w[i] - bias[i] < 0) {
+                    continue;
+                }
+
+                let mut bc = w.map(|w| w as f32 / area as f32);
+                if flipped {
+                    bc.swap(1, 2);
+                }
+                out.push(([x as u32, y as u32], bc));
+            }
+        }
+        out
+    }
+}
+/* #endregion */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::cpu::*;
+
+    const SIZE: (u32, u32) = (32, 32);
+
+    fn coverage(triangles: &[[[f32; 2]; 3]]) -> Vec<u32> {
+        let mut out = vec![0; (SIZE.0 * SIZE.1) as usize];
+        for [a, b, c] in triangles {
+            for (p, _) in rasterize(*a, *b, *c, SIZE) {
+                out[(p[0] + p[1] * SIZE.0) as usize] += 1;
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn shared_edges_cover_pixels_once() {
+        // a grid of quads split along both diagonals, with every corner
+        // landing on pixel centers and edges running through them
+        let mut triangles = Vec::new();
+        for i in 0..4 {
+            for j in 0..4 {
+                let (x0, y0) = (i as f32 * 8.0 + 0.5, j as f32 * 8.0 + 0.5);
+                let (x1, y1) = (x0 + 8.0, y0 + 8.0);
+                let mid = [x0 + 4.0, y0 + 4.0];
+                triangles.push([[x0, y0], [x1, y0], mid]);
+                triangles.push([[x1, y0], [x1, y1], mid]);
+                triangles.push([[x1, y1], [x0, y1], mid]);
+                triangles.push([[x0, y1], [x0, y0], mid]);
+            }
+        }
+
+        let cover = coverage(&triangles);
+        for y in 0..SIZE.1 {
+            for x in 0..SIZE.0 {
+                assert_eq!(
+                    cover[(x + y * SIZE.0) as usize], 1,
+                    "pixel ({}, {})", x, y
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn winding_does_not_change_coverage() {
+        let a = [3.2, 1.7];
+        let b = [20.9, 6.1];
+        let c = [9.4, 25.3];
+        let forward = rasterize(a, b, c, SIZE)
+            .into_iter().map(|(p, _)| p).collect::<Vec<_>>();
+        let backward = rasterize(a, c, b, SIZE)
+            .into_iter().map(|(p, _)| p).collect::<Vec<_>>();
+        assert_eq!(forward, backward);
+    }
+
+    #[test]
+    fn thin_triangles_still_cover_centers() {
+        // less than a pixel wide, but straddling a column of centers
+        let cover = coverage(&[[[4.3, 2.0], [4.7, 2.0], [4.5, 20.0]]]);
+        assert!(cover[(4 + 10 * SIZE.0) as usize] == 1);
+    }
+
+    #[test]
+    fn covered_pixels_land_in_listed_bins() {
+        let size = (100, 60);
+        for [a, b, c] in [
+            [[3.2, 1.7], [90.9, 6.1], [9.4, 55.3]],
+            [[-40.0, -20.0], [300.0, 10.0], [20.0, 200.0]],
+            [[17.5, 33.0], [18.5, 33.0], [18.0, 47.9]],
+        ] {
+            let bins = bins(a, b, c, size);
+            for (p, _) in rasterize(a, b, c, size) {
+                let bin = p.map(|p| p / constants::BIN_SIZE);
+                assert!(bins.contains(&bin), "pixel {:?} missed", p);
+            }
+        }
+    }
+
+    #[test]
+    fn clipped_corners_stay_in_bounds() {
+        let size = (100, 60);
+        let poly = clip(&[
+            [-3000.0, -2000.0, 6000.0],
+            [3000.0, -2000.0, -6000.0],
+            [50.0, 4000.0, 0.0],
+        ], size);
+        let guard = constants::GUARD_BAND as f32;
+        assert!(poly.len() >= 3 && poly.len() <= 9);
+        for p in poly {
+            assert!(p[0] >= -guard - 1e-3);
+            assert!(p[0] <= size.0 as f32 + guard + 1e-3);
+            assert!(p[1] >= -guard - 1e-3);
+            assert!(p[1] <= size.1 as f32 + guard + 1e-3);
+            assert!(p[2] <= constants::NEAR_PLANE + 1e-3);
+            assert!(p[2] >= constants::FAR_PLANE - 1e-3);
+        }
+    }
+
+    #[test]
+    fn clipped_neighbours_stay_watertight() {
+        // a quad over the whole screen, split along a diagonal that both
+        // halves have to clip
+        let size = (100, 60);
+        let quad = [
+            [-300.3, -200.7, 0.0],
+            [400.1, -150.2, 0.0],
+            [350.6, 300.9, 0.0],
+            [-250.4, 260.5, 0.0],
+        ];
+        let mut cover = vec![0; (size.0 * size.1) as usize];
+        for tri in [
+            [quad[0], quad[1], quad[2]],
+            [quad[2], quad[3], quad[0]],
+        ] {
+            let poly = clip(&tri, size);
+            for i in 2..poly.len() {
+                let [a, b, c] = [poly[0], poly[i - 1], poly[i]]
+                    .map(|p| [p[0], p[1]]);
+                for (p, _) in rasterize(a, b, c, size) {
+                    cover[(p[0] + p[1] * size.0) as usize] += 1;
+                }
+            }
+        }
+        assert!(cover.iter().all(|&c| c == 1));
+    }
+
+    #[test]
+    fn triangles_past_the_near_plane_vanish() {
+        let z = constants::NEAR_PLANE + 10.0;
+        let poly = clip(&[
+            [10.0, 10.0, z],
+            [50.0, 10.0, z],
+            [10.0, 50.0, z],
+        ], (100, 60));
+        assert!(poly.is_empty());
+    }
+
+    #[test]
+    fn barycentrics_reproduce_pixel_centers() {
+        let (a, b, c) = ([1.0, 1.0], [30.0, 4.0], [6.0, 28.0]);
+        for (p, bc) in rasterize(a, b, c, SIZE) {
+            let x = bc[0] * a[0] + bc[1] * b[0] + bc[2] * c[0];
+            let y = bc[0] * a[1] + bc[1] * b[1] + bc[2] * c[1];
+            assert!((x - (p[0] as f32 + 0.5)).abs() < 1e-3);
+            assert!((y - (p[1] as f32 + 0.5)).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn edge_functions_fit_in_i32() {
+        // two products of differences across the biggest bounding box
+        let extent = (constants::MAX_TRIANGLE_EXTENT as i64)
+            << constants::SUBPIXEL_BITS;
+        assert!(2 * extent * extent <= i32::MAX as i64);
+    }
+
+    #[test]
+    fn full_screen_triangles_survive_every_resolution() {
+        let most = (constants::MAX_RESOLUTION, constants::MAX_RESOLUTION);
+        for size in constants::RESOLUTIONS.into_iter().chain([most]) {
+            // far past every side, the way first person's ground is
+            let poly = clip(&[
+                [-1.0e5, -1.0e5, 0.0],
+                [1.0e5, -1.0e5, 0.0],
+                [0.0, 1.0e5, 0.0],
+            ], size);
+            assert!(poly.len() >= 3, "{:?}", size);
+            let mut covered = 0;
+            for i in 2..poly.len() {
+                let [a, b, c] = [poly[0], poly[i - 1], poly[i]]
+                    .map(|p| snap([p[0], p[1]]));
+                assert!(pixel_bounds(a, b, c, size).is_some(), "{:?}", size);
+                if size != most {
+                    let [a, b, c] = [poly[0], poly[i - 1], poly[i]]
+                        .map(|p| [p[0], p[1]]);
+                    covered += rasterize(a, b, c, size).len();
+                }
+            }
+            if size != most {
+                assert_eq!(covered, (size.0 * size.1) as usize);
+            }
+        }
+    }
+
+    #[test]
+    fn buffers_fit_at_the_largest_resolution() {
+        let most = (constants::MAX_RESOLUTION, constants::MAX_RESOLUTION);
+        let limit = wgpu::Limits::downlevel_defaults()
+            .max_storage_buffer_binding_size as u64;
+        assert!(bin_tris_size(most) <= limit);
+        assert!(counters_size(most) <= limit);
+        assert!(SCREEN_TRIANGLES_SIZE <= limit);
+        assert!(crate::game::screen::gbuffer::g_buffer_size(most) <= limit);
+    }
+
+    #[test]
+    fn full_bins_keep_the_first_triangles() {
+        // two bins across and down, and a pixel-sized triangle in the
+        // first one for every place it has, and one more
+        let cap = constants::BIN_CAPACITY;
+        let tiny = [[2.0, 2.0], [3.0, 2.0], [2.0, 3.0]];
+        let mut triangles = vec![tiny; cap as usize];
+        let (counters, lists) = bin_all(&triangles, SIZE);
+        let stats = BinStats::from_counters(&counters);
+        assert_eq!(stats.fullest_bin, cap);
+        assert!(!stats.overflowed());
+        assert_eq!(lists[0].len(), cap as usize);
+
+        // the one too many is counted, and the other bins carry on
+        triangles.push(tiny);
+        triangles.push([[20.0, 20.0], [21.0, 20.0], [20.0, 21.0]]);
+        let (counters, lists) = bin_all(&triangles, SIZE);
+        let stats = BinStats::from_counters(&counters);
+        assert_eq!(stats.dropped_entries, 1);
+        assert_eq!(stats.dropped_triangles, 0);
+        assert!(stats.overflowed());
+        assert_eq!(lists[0], (0..cap).collect::<Vec<_>>());
+        assert_eq!(lists[3], vec![cap + 1]);
+    }
+
+    #[test]
+    fn triangles_past_the_buffer_are_counted() {
+        let mut counters = vec![0; 5];
+        counters[0] = constants::MAX_SCREEN_TRIANGLES + 3;
+        let stats = BinStats::from_counters(&counters);
+        assert_eq!(stats.dropped_triangles, 3);
+        assert!(stats.overflowed());
+    }
+}
@@ -0,0 +1,418 @@
+// The whole frame done on the cpu: projection, clipping and rasterizing
+// into the G-buffer, then lighting into the packed screen buffer. Each
+// step calls the CPU references next to the shaders they mirror, so the
+// output matches the gpu's to within rounding, and golden images of it
+// catch regressions in the camera matrices and the shader math.
+
+use std::path::PathBuf;
+
+use nalgebra::Matrix4;
+
+use crate::constants;
+use crate::game::camera::{ self, Camera, ProjectionMode, BEHIND_CAMERA };
+use crate::game::material::{ MATERIALS, UNLIT };
+use crate::game::tile::Tile;
+use crate::game::tile::mapdata::meshes;
+use super::cull::Projection;
+use super::gbuffer::G_BUFFER_NUMS;
+use super::gbuffer::cpu::{
+    color_to_int, int_to_color, depth_buf_to_z, int_to_normal, normal_to_int,
+    z_to_depth_buf,
+};
+use super::{ offscreen, raster, renderer, shadow };
+
+const SIZE: (u32, u32) = constants::SCREEN_PIXELS;
+
+/// What the gpu has after a frame, in the same packing.
+pub struct Frame {
+    /// `gbuffer::LAYOUT` channels for every pixel
+    pub g_buffer: Vec<u32>,
+    /// one packed color per pixel, what `copy.wgsl` shows
+    pub screen: Vec<u32>,
+}
+
+fn channel(x: u32, y: u32, i: u32) -> usize {
+    ((x + y * SIZE.0) * G_BUFFER_NUMS + i) as usize
+}
+
+const DEPTH: u32 = 0;
+const COLOR: u32 = 1;
+const NORMAL: u32 = 2;
+const MATERIAL: u32 = 3;
+const EMISSIVE: u32 = 4;
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn normalize(v: [f32; 3]) -> [f32; 3] {
+    let len = dot(v, v).sqrt();
+    v.map(|x| x / len)
+}
+
+/// `triangle_normal` in binning.wgsl
+fn triangle_normal(a: [f32; 3], b: [f32; 3], c: [f32; 3]) -> [f32; 3] {
+    let (u, v) = (sub(b, c), sub(a, c));
+    normalize([
+        u[1] * v[2] - u[2] * v[1],
+        u[2] * v[0] - u[0] * v[2],
+        u[0] * v[1] - u[1] * v[0],
+    ])
+}
+
+/// `bin_main` and `raster_main` together, for every tile in `tiles`.
+fn rasterize(projection: &Projection, tiles: &[Tile]) -> Vec<u32> {
+    let mut g_buffer = vec![0; (SIZE.0 * SIZE.1 * G_BUFFER_NUMS) as usize];
+    for tile in tiles.iter() {
+        let mesh = &meshes()[tile.get_mesh()];
+        let mat: Matrix4<f32> = tile.get_mat().cast();
+        // vertices are a position and a color, 4 floats each
+        let vertex = |i: u32| -> [f32; 8] {
+            let start = 32 * i as usize;
+            std::array::from_fn(|j| f32::from_le_bytes(
+                mesh.vertices[start + 4 * j..start + 4 * j + 4]
+                    .try_into().unwrap()
+            ))
+        };
+        let indices: &[u32] = bytemuck::cast_slice(&mesh.indices);
+
+        for (tri, &mat_id) in indices.chunks_exact(3).zip(&mesh.materials) {
+            let verts = [0, 1, 2].map(|k| vertex(tri[k]));
+            let in_eye_space = projection.mode == ProjectionMode::FirstPerson;
+            let pos = verts.map(|v| {
+                let v = [v[0], v[1], v[2], v[3]];
+                if in_eye_space {
+                    projection.eye(&mat, v)
+                } else {
+                    projection.project(&mat, v)
+                }
+            });
+            let norm = if in_eye_space {
+                camera::first_person_normal(
+                    pos[0], pos[1], pos[2], projection.pix)
+            } else {
+                triangle_normal(pos[0], pos[1], pos[2])
+            };
+            if norm[2] < 0.0 {
+                continue;
+            }
+
+            let surface = &MATERIALS[mat_id as usize];
+            let corners = [0, 1, 2].map(|k| {
+                let (p, v) = (pos[k], verts[k]);
+                let d = surface.diffuse;
+                [p[0], p[1], p[2],
+                    v[4] * d[0], v[5] * d[1], v[6] * d[2], v[7] * d[3]]
+            });
+            let mut poly = corners.to_vec();
+            if in_eye_space {
+                poly = raster::cpu::clip_plane(
+                    &poly, 2, 1.0, constants::NEAR_DISTANCE);
+                for corner in poly.iter_mut() {
+                    let p = camera::first_person(
+                        [corner[0], corner[1], corner[2]], projection.pix);
+                    corner[..3].copy_from_slice(&p);
+                }
+            }
+            let poly = raster::cpu::clip(&poly, SIZE);
+
+            let normal = normal_to_int(norm);
+            let emissive = color_to_int(surface.emissive);
+            for i in 2..poly.len() {
+                let tri = [poly[0], poly[i - 1], poly[i]];
+                let colors = tri.map(|v| int_to_color(
+                    color_to_int([v[3], v[4], v[5], v[6]])));
+                let [a, b, c] = tri.map(|v| [v[0], v[1]]);
+                for ([x, y], bc) in raster::cpu::rasterize(a, b, c, SIZE) {
+                    let d = bc[0] * tri[0][2]
+                        + bc[1] * tri[1][2]
+                        + bc[2] * tri[2][2];
+                    let range = constants::FAR_PLANE..=constants::NEAR_PLANE;
+                    if !range.contains(&d) {
+                        continue;
+                    }
+                    let depth = z_to_depth_buf(d);
+                    if depth <= g_buffer[channel(x, y, DEPTH)] {
+                        continue;
+                    }
+
+                    let col = std::array::from_fn(|j|
+                        bc[0] * colors[0][j]
+                        + bc[1] * colors[1][j]
+                        + bc[2] * colors[2][j]);
+                    g_buffer[channel(x, y, DEPTH)] = depth;
+                    g_buffer[channel(x, y, COLOR)] = color_to_int(col);
+                    g_buffer[channel(x, y, NORMAL)] = normal;
+                    g_buffer[channel(x, y, MATERIAL)] = mat_id;
+                    g_buffer[channel(x, y, EMISSIVE)] = emissive;
+                }
+            }
+        }
+    }
+    g_buffer
+}
+
+/// `main` in lighting.wgsl: ambient and glow, then every light added up
+/// before the pixel is stored once.
+fn light(projection: &Projection, camera: &Camera, g_buffer: &[u32])
+    -> Vec<u32>
+{
+    let depths = (0..SIZE.0 * SIZE.1)
+        .map(|i| g_buffer[(i * G_BUFFER_NUMS + DEPTH) as usize])
+        .collect::<Vec<_>>();
+    let lights = renderer::lights(camera);
+    let mut screen = vec![0; (SIZE.0 * SIZE.1) as usize];
+
+    for y in 0..SIZE.1 {
+        for x in 0..SIZE.0 {
+            let idx = (x + y * SIZE.0) as usize;
+            let surface =
+                &MATERIALS[g_buffer[channel(x, y, MATERIAL)] as usize];
+            let unlit = surface.flags & UNLIT != 0;
+
+            let col = int_to_color(g_buffer[channel(x, y, COLOR)]);
+            let glow = int_to_color(g_buffer[channel(x, y, EMISSIVE)]);
+            let ambient = if unlit { 1.0 } else { 0.05 };
+            let mut total = [
+                ambient * col[0] + glow[3] * glow[0],
+                ambient * col[1] + glow[3] * glow[1],
+                ambient * col[2] + glow[3] * glow[2],
+                col[3],
+            ];
+
+            let depth = g_buffer[channel(x, y, DEPTH)];
+            for light in lights.iter() {
+                if light.position[3] < 0.0 || depth == 0 || unlit {
+                    continue;
+                }
+                let l_pos = projection.project(
+                    &Matrix4::identity(), light.position);
+                if l_pos[2] >= BEHIND_CAMERA {
+                    continue;
+                }
+                let p = [x as f32, y as f32, depth_buf_to_z(depth)];
+                let norm = int_to_normal(g_buffer[channel(x, y, NORMAL)]);
+
+                let ray = sub(p, l_pos).map(|r|
+                    r / (projection.map_scale * projection.pix.0));
+                if dot(ray, ray) == 0.0 {
+                    continue;
+                }
+                let brightness = light.color[3] / (dot(ray, ray) * 100.0)
+                    * shadow::cpu::shadow(&depths, SIZE, p, l_pos);
+
+                let to_light = normalize(ray).map(|r| -r);
+                let diffuse = dot(to_light, norm).clamp(0.0, 1.0);
+                let halfway = normalize(
+                    [to_light[0], to_light[1], to_light[2] + 1.0]);
+                let specular = if diffuse > 0.0 {
+                    surface.specular
+                        * dot(norm, halfway).max(0.0).powf(surface.exponent)
+                } else {
+                    0.0
+                };
+
+                for (j, total) in total.iter_mut().enumerate() {
+                    let l = if j < 3 { light.color[j] } else { 1.0 };
+                    let s = if j < 3 { specular } else { 0.0 };
+                    *total += brightness * l * (col[j] * diffuse + s);
+                }
+            }
+            screen[idx] = color_to_int(total);
+        }
+    }
+    screen
+}
+
+pub fn render(camera: &Camera, tiles: &[Tile]) -> Frame {
+    let projection = Projection::new(camera, SIZE);
+    let g_buffer = rasterize(&projection, tiles);
+    let screen = light(&projection, camera, &g_buffer);
+    Frame { g_buffer, screen }
+}
+
+/// Rounds a channel the way writing to an sRGB target does.
+fn srgb_encode(num: u32) -> u8 {
+    let c = num as f32 / 255.0;
+    let s = if c <= 0.0031308 {
+        12.92 * c
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    };
+    (s * 255.0 + 0.5) as u8
+}
+
+/// `copy.wgsl` into an `offscreen::FORMAT` texture, so it matches what
+/// `Screen::capture` reads back.
+pub fn to_rgba(screen: &[u32]) -> Vec<u8> {
+    screen.iter().flat_map(|&v| [
+        srgb_encode(v >> 24),
+        srgb_encode((v >> 16) % 256),
+        srgb_encode((v >> 8) % 256),
+        255,
+    ]).collect()
+}
+
+/// Pixels whose channels differ by more than `tolerance` anywhere.
+fn mismatches(a: &[u8], b: &[u8], tolerance: u8) -> usize {
+    assert_eq!(a.len(), b.len());
+    a.chunks_exact(4).zip(b.chunks_exact(4))
+        .filter(|(p, q)| p.iter().zip(q.iter())
+            .any(|(x, y)| x.abs_diff(*y) > tolerance))
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use super::*;
+    use crate::game::tile::permutation::IDENTITY;
+
+    /// The center tile and its four neighbours.
+    fn tiles() -> Vec<Tile> {
+        std::iter::once(IDENTITY)
+            .chain(IDENTITY.neighbors())
+            .map(Tile::new)
+            .collect()
+    }
+
+    /// A camera `distance` out from the origin, looking `angle` away from
+    /// straight ahead, in the same direction it moved.
+    fn camera(distance: f64, angle: f64) -> Camera {
+        let (s, c) = angle.sin_cos();
+        let mut out = Camera::new();
+        out.set_camera(
+            [s * distance.sinh(), c * distance.sinh(), 0.0, distance.cosh()],
+            [s * distance.cosh(), c * distance.cosh(), distance.sinh()],
+        );
+        out
+    }
+
+    /// `camera(0.4, 0.5)`, seen from the eye instead of from above.
+    fn first_person() -> Camera {
+        let mut out = camera(0.4, 0.5);
+        out.set_projection(ProjectionMode::FirstPerson);
+        out
+    }
+
+    fn golden_path(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("src/game/screen/golden")
+            .join(format!("{}.png", name))
+    }
+
+    /// Compares against the checked-in image, or writes it instead when
+    /// `UPDATE_GOLDEN` is set.
+    fn check_golden(name: &str, rgba: &[u8]) {
+        let path = golden_path(name);
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            offscreen::save_png(&path, SIZE, rgba).unwrap();
+            return;
+        }
+
+        let file = File::open(&path).unwrap_or_else(|_| panic!(
+            "no {}, run with UPDATE_GOLDEN=1 to make it", path.display()));
+        let mut reader = png::Decoder::new(file).read_info().unwrap();
+        let mut golden = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut golden).unwrap();
+
+        // a little room for `powf` and friends rounding differently
+        let off = mismatches(rgba, &golden, 2);
+        assert!(
+            off * 1000 <= rgba.len() / 4,
+            "{}: {} pixels differ from {}", name, off, path.display()
+        );
+    }
+
+    #[test]
+    fn golden_origin() {
+        let frame = render(&Camera::new(), &tiles());
+        check_golden("origin", &to_rgba(&frame.screen));
+    }
+
+    #[test]
+    fn golden_moved_and_turned() {
+        let frame = render(&camera(0.4, 0.5), &tiles());
+        check_golden("moved_and_turned", &to_rgba(&frame.screen));
+    }
+
+    #[test]
+    fn golden_first_person() {
+        let frame = render(&first_person(), &tiles());
+        check_golden("first_person", &to_rgba(&frame.screen));
+    }
+
+    #[test]
+    fn every_covered_pixel_has_a_normal_facing_the_camera() {
+        for camera in [Camera::new(), first_person()] {
+            let frame = render(&camera, &tiles());
+            let pixels = frame.g_buffer.chunks_exact(G_BUFFER_NUMS as usize);
+            for pixel in pixels {
+                if pixel[DEPTH as usize] != 0 {
+                    let norm = int_to_normal(pixel[NORMAL as usize]);
+                    assert!(norm[2] >= -1e-3);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn gpu_matches_reference() {
+        use crate::config::{ Config, RasterBackend };
+        use crate::game::screen::{ self, Screen };
+
+        // any adapter will do, software ones included
+        let instance = wgpu::Instance::new(wgpu::Backends::all());
+        let adapter = pollster::block_on(
+            screen::request_adapter(&instance, None));
+        let Some(adapter) = adapter else {
+            eprintln!("no graphics adapter, not comparing with the gpu");
+            return;
+        };
+        let downlevel = adapter.get_downlevel_capabilities().flags;
+
+        for raster in [RasterBackend::Compute, RasterBackend::Hardware] {
+            if !downlevel.contains(screen::required_downlevel(raster)) {
+                eprintln!("{:?} can't run here, not comparing it", raster);
+                continue;
+            }
+            let config = Config {
+                raster,
+                minimap: false,
+                ..Config::default()
+            };
+            let mut screen =
+                pollster::block_on(Screen::headless(&config)).unwrap();
+            // every mode, so `w_to_c` is held to `Projection::project`
+            let cameras = ProjectionMode::ALL.map(|mode| {
+                let mut out = camera(0.4, 0.5);
+                out.set_projection(mode);
+                out
+            });
+            for camera in std::iter::once(Camera::new()).chain(cameras) {
+                let mut tiles = tiles();
+                for tile in tiles.iter_mut() {
+                    screen.acquire_tile(tile);
+                }
+                let gpu = screen.capture(&camera, &camera.frame(), &tiles);
+                let cpu = to_rgba(&render(&camera, &tiles).screen);
+                for tile in tiles.iter_mut() {
+                    screen.release_tile(tile);
+                }
+
+                // edges can round either way on different hardware
+                let off = mismatches(&gpu, &cpu, 8);
+                assert!(
+                    off * 100 <= gpu.len() / 4,
+                    "{:?} {:?}: {} pixels differ",
+                    raster, camera.get_projection(), off
+                );
+            }
+        }
+    }
+}
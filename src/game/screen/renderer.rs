@@ -1,7 +1,20 @@
+use std::cell::Cell;
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicU8, Ordering };
 use bytemuck;
+use nalgebra::Matrix4;
+use crate::config::RasterBackend;
 use crate::constants;
-use crate::game::camera::Camera;
+use crate::game::camera::{ Camera, ProjectionMode };
+use crate::game::material::MATERIALS;
 use crate::game::tile::Tile;
+use super::{ gbuffer, raster, shaders };
+use super::raster::BinStats;
+use super::shaders::PcBinding;
+use super::cull::{ CullStats, Projection };
+use super::geometry::{ Geometry, PoolStats };
+use super::hardware::HardwareRaster;
+use super::minimap::{ self, Minimap };
 use wgpu::{
     util::DeviceExt,
     ComputePassDescriptor,
@@ -16,45 +29,119 @@ pub struct Vertex {
 }
 /* #endregion */
 
+/// Every light in the scene, as world position and rgb + intensity: one
+/// that follows the camera and a dim green one that stays put. In first
+/// person the camera's is carried a little way ahead, where it can be
+/// seen.
+pub fn lights(camera: &Camera) -> [Vertex; 2] {
+    let pos = match camera.get_projection() {
+        ProjectionMode::FirstPerson =>
+            camera.ahead(constants::LAMP_DISTANCE),
+        _ => *camera.get_position(),
+    };
+    [
+        Vertex {
+            position: [pos[0] as f32, pos[1] as f32, 0.1, pos[3] as f32],
+            color: [1.0, 1.0, 1.0, 0.5],
+        },
+        Vertex {
+            position: [0.0, 0.5, 0.1, 1.118034],
+            color: [0.0, 1.0, 0.0, 0.3],
+        },
+    ]
+}
+
+/// What's in a frame: the camera it's seen through, the tiles it has, and
+/// the player's frame, which the minimap goes around.
+pub struct Scene<'a> {
+    pub camera: &'a Camera,
+    pub player: &'a Matrix4<f64>,
+    pub tiles: &'a [Tile],
+}
+
 pub struct Renderer {
-    vertex_bind_group_layout: wgpu::BindGroupLayout,
+    geometry: Geometry,
+    cull_stats: Cell<CullStats>,
+    bin_stats: Cell<BinStats>,
+
+    material_bind_group: wgpu::BindGroup,
 
     light_buffer: wgpu::Buffer,
     light_bind_group: wgpu::BindGroup,
 
-    g_buffer: wgpu::Buffer,
-    g_buffer_bind_group: wgpu::BindGroup,
-
-    screen_buffer: wgpu::Buffer,
-    screen_bind_group: wgpu::BindGroup,
+    // the internal resolution, everything in `buffers` is sized by it
+    size: (u32, u32),
+    buffers: ScreenBuffers,
+    screen_bind_group_layout: wgpu::BindGroupLayout,
+    bin_bind_group_layout: wgpu::BindGroupLayout,
 
     camera_buffer: wgpu::Buffer,
     camera_bind_group: wgpu::BindGroup,
 
+    pc: PcBinding,
+    // only read with `PcBinding::Uniform`
+    pc_buffer: wgpu::Buffer,
+
     lighting_pipeline: wgpu::ComputePipeline,
+    bin_pipeline: wgpu::ComputePipeline,
     g_buffer_pipeline: wgpu::ComputePipeline,
+    // set when the G-buffer is drawn with `RasterBackend::Hardware`
+    hardware: Option<HardwareRaster>,
 
     render_pipeline: wgpu::RenderPipeline,
+    minimap: Minimap,
 }
 
 impl Renderer {
     pub fn new(
         device: &wgpu::Device,
-        config: &wgpu::SurfaceConfiguration) -> Renderer
+        format: wgpu::TextureFormat,
+        backend: RasterBackend,
+        size: (u32, u32),
+        pc: PcBinding) -> Renderer
     {
+        /* #region MATERIAL TABLE */
+        let material_buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Material Buffer"),
+                contents: bytemuck::cast_slice(&MATERIALS),
+                usage: wgpu::BufferUsages::STORAGE,
+            }
+        );
+        let material_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::COMPUTE
+                            | wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage
+                                { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+        let material_bind_group = device.create_bind_group(
+            &wgpu::BindGroupDescriptor {
+                label: Some("material_bind_group"),
+                layout: &material_bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: material_buffer.as_entire_binding(),
+                    }]
+                });
+        /* #endregion */
+
         let light_buffer = device.create_buffer_init(
             &wgpu::util::BufferInitDescriptor {
                 label: Some("Light Buffer"),
-                contents: bytemuck::cast_slice(&[
-                    Vertex {
-                        position: [0.0, 0.0, 0.1, 1.0],
-                        color: [1.0, 1.0, 1.0, 0.8],
-                    },
-                    Vertex {
-                        position: [0.0, 0.5, 0.1, 1.11803398875],
-                        color: [0.0, 1.0, 0.0, 0.3],
-                    },
-                ]),
+                contents: bytemuck::cast_slice(&lights(&Camera::new())),
                 usage: wgpu::BufferUsages::STORAGE
                     | wgpu::BufferUsages::COPY_DST,
             }
@@ -74,6 +161,17 @@ impl Renderer {
                         },
                         count: None,
                     },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage
+                                { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
                 ],
             });
         let light_bind_group = device.create_bind_group(
@@ -89,6 +187,10 @@ impl Renderer {
                                 offset: 0,
                                 size: None,
                             })
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: material_buffer.as_entire_binding(),
                     }]
                 });
 
@@ -99,7 +201,8 @@ impl Renderer {
                 entries: &[
                     wgpu::BindGroupLayoutEntry {
                         binding: 0,
-                        visibility: wgpu::ShaderStages::COMPUTE,
+                        visibility: wgpu::ShaderStages::COMPUTE
+                            | wgpu::ShaderStages::VERTEX,
                         ty: wgpu::BindingType::Buffer{
                             ty: wgpu::BufferBindingType::Storage
                                 { read_only: true },
@@ -110,7 +213,8 @@ impl Renderer {
                     },
                     wgpu::BindGroupLayoutEntry {
                         binding: 1,
-                        visibility: wgpu::ShaderStages::COMPUTE,
+                        visibility: wgpu::ShaderStages::COMPUTE
+                            | wgpu::ShaderStages::VERTEX,
                         ty: wgpu::BindingType::Buffer{
                             ty: wgpu::BufferBindingType::Storage
                                 { read_only: true },
@@ -121,7 +225,20 @@ impl Renderer {
                     },
                     wgpu::BindGroupLayoutEntry {
                         binding: 2,
-                        visibility: wgpu::ShaderStages::COMPUTE,
+                        visibility: wgpu::ShaderStages::COMPUTE
+                            | wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer{
+                            ty: wgpu::BufferBindingType::Storage
+                                { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::COMPUTE
+                            | wgpu::ShaderStages::VERTEX,
                         ty: wgpu::BindingType::Buffer{
                             ty: wgpu::BufferBindingType::Storage
                                 { read_only: true },
@@ -130,33 +247,64 @@ impl Renderer {
                         },
                         count: None,
                     },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::COMPUTE
+                            | wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer{
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
                 ],
             });
+        let geometry = Geometry::new(device, &vertex_bind_group_layout);
         /* #endregion */
     
         /* #region CAMERA STUFF */
         let camera_buffer = device.create_buffer_init(
             &wgpu::util::BufferInitDescriptor {
                 label: Some("Camera Buffer"),
-                contents: bytemuck::cast_slice(&[0.0f32; 32]),
+                contents: bytemuck::bytes_of(&Camera::new().uniform(1.0)),
                 usage: wgpu::BufferUsages::UNIFORM
                     | wgpu::BufferUsages::COPY_DST,
             }
         );
+        let pc_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Push Constant Buffer"),
+            size: shaders::PUSH_CONSTANTS_SIZE as u64,
+            usage: wgpu::BufferUsages::UNIFORM
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
         let camera_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 label: None,
                 entries: &[
                     wgpu::BindGroupLayoutEntry {
                         binding: 0,
-                        visibility: wgpu::ShaderStages::COMPUTE,
+                        visibility: wgpu::ShaderStages::COMPUTE
+                            | wgpu::ShaderStages::VERTEX,
                         ty: wgpu::BindingType::Buffer {
                             ty: wgpu::BufferBindingType::Uniform,
                             has_dynamic_offset: false,
                             min_binding_size: None,
                         },
                         count: None,
-                    }
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::COMPUTE
+                            | wgpu::ShaderStages::VERTEX_FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
                 ],
             }
         );
@@ -168,22 +316,16 @@ impl Renderer {
                     wgpu::BindGroupEntry {
                         binding: 0,
                         resource: camera_buffer.as_entire_binding(),
-                    }
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: pc_buffer.as_entire_binding(),
+                    },
                 ],
             });
         /* #endregion */
 
-        /* #region THE SCREEN WE DRAW TO */
-        let screen_buffer =
-            device.create_buffer(&wgpu::BufferDescriptor {
-                label: None,
-                size: (constants::SCREEN_PIXELS.0
-                    * constants::SCREEN_PIXELS.1
-                    * 4) as u64,
-                usage: wgpu::BufferUsages::COPY_DST
-                    | wgpu::BufferUsages::STORAGE,
-                mapped_at_creation: false,
-            });
+        /* #region THE SCREEN AND G-BUFFER */
         let screen_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 label: None,
@@ -201,34 +343,6 @@ impl Renderer {
                     },
                 ],
             });
-        let screen_bind_group = device.create_bind_group(
-            &wgpu::BindGroupDescriptor {
-                label: Some("compute output"),
-                layout: &screen_bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::Buffer(
-                            wgpu::BufferBinding {
-                                buffer: &screen_buffer,
-                                offset: 0,
-                                size: None
-                            }
-                        ),
-                    },
-                ],
-            });
-        /* #endregion */
-
-        /* #region THE G-BUFFER */
-        let g_buffer =
-            device.create_buffer(&wgpu::BufferDescriptor {
-                label: None,
-                size: (constants::G_BUFFER_SIZE) as u64,
-                usage: wgpu::BufferUsages::COPY_DST
-                    | wgpu::BufferUsages::STORAGE,
-                mapped_at_creation: false,
-            });
         let g_buffer_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 label: None,
@@ -246,57 +360,95 @@ impl Renderer {
                     },
                 ],
             });
-        let g_buffer_bind_group = device.create_bind_group(
-            &wgpu::BindGroupDescriptor {
-                label: Some("g-buffer"),
-                layout: &screen_bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::Buffer(
-                            wgpu::BufferBinding {
-                                buffer: &g_buffer,
-                                offset: 0,
-                                size: None
-                            }
-                        ),
-                    },
-                ],
+        /* #endregion */
+
+        /* #region TRIANGLE BINS */
+        let bin_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[0, 1, 2].map(|binding|
+                    wgpu::BindGroupLayoutEntry {
+                        binding,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage
+                                { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    }),
             });
         /* #endregion */
-        
+
+        let buffers = ScreenBuffers::new(
+            device, size, &screen_bind_group_layout, &bin_bind_group_layout);
+
         /* #region G-BUFFER PIPELINE SETUP */
+        let bin_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: None,
+            source: wgpu::ShaderSource::Wgsl(shaders::binning(pc).into()),
+        });
+        let bin_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("binning"),
+                bind_group_layouts: &[
+                    &bin_bind_group_layout,
+                    &camera_bind_group_layout,
+                    &vertex_bind_group_layout,
+                    &material_bind_group_layout,
+                ],
+                push_constant_ranges: &pc.ranges(wgpu::ShaderStages::COMPUTE),
+            });
+        let bin_pipeline =
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: None,
+                layout: Some(&bin_pipeline_layout),
+                module: &bin_shader,
+                entry_point: "bin_main",
+            });
+
         let g_buffer_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: None,
-            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/g_buffer.wgsl").into()),
+            source: wgpu::ShaderSource::Wgsl(shaders::g_buffer(pc).into()),
         });
         let g_buffer_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("g_buffer"),
                 bind_group_layouts: &[
                     &g_buffer_bind_group_layout,
+                    &bin_bind_group_layout,
                     &camera_bind_group_layout,
-                    &vertex_bind_group_layout,
                 ],
-                push_constant_ranges: &[wgpu::PushConstantRange{
-                    stages: wgpu::ShaderStages::COMPUTE,
-                    range: 0..32,
-                }],
+                push_constant_ranges: &pc.ranges(wgpu::ShaderStages::COMPUTE),
             });
         let g_buffer_pipeline =
             device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
                 label: None,
                 layout: Some(&g_buffer_pipeline_layout),
                 module: &g_buffer_shader,
-                entry_point: "main",
+                entry_point: "raster_main",
             });
         /* #endregion */
         
+        let hardware = match backend {
+            RasterBackend::Compute => None,
+            RasterBackend::Hardware => Some(HardwareRaster::new(
+                device,
+                &camera_bind_group_layout,
+                &vertex_bind_group_layout,
+                &material_bind_group_layout,
+                &g_buffer_bind_group_layout,
+                size,
+                pc,
+            )),
+        };
+
         /* #region LIGHTING PIPELINE SETUP */
 
         let lighting_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: None,
-            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/lighting.wgsl").into()),
+            source: wgpu::ShaderSource::Wgsl(shaders::lighting(pc).into()),
         });
 
         let lighting_pipeline_layout =
@@ -308,10 +460,7 @@ impl Renderer {
                     &camera_bind_group_layout,
                     &light_bind_group_layout,
                 ],
-                push_constant_ranges: &[wgpu::PushConstantRange{
-                    stages: wgpu::ShaderStages::COMPUTE,
-                    range: 0..32,
-                }],
+                push_constant_ranges: &pc.ranges(wgpu::ShaderStages::COMPUTE),
             });
         let lighting_pipeline =
             device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
@@ -325,17 +474,17 @@ impl Renderer {
         /* #region RENDER PIPELINE SETUP */
         let copy_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: None,
-            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/copy.wgsl").into()),
+            source: wgpu::ShaderSource::Wgsl(shaders::copy(pc).into()),
         });
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("render"),
-                bind_group_layouts: &[&g_buffer_bind_group_layout],
-                push_constant_ranges: &[
-                    wgpu::PushConstantRange {
-                        stages: wgpu::ShaderStages::VERTEX_FRAGMENT,
-                        range: 0..32,
-                    }],
+                bind_group_layouts: &[
+                    &g_buffer_bind_group_layout,
+                    &camera_bind_group_layout,
+                ],
+                push_constant_ranges:
+                    &pc.ranges(wgpu::ShaderStages::VERTEX_FRAGMENT),
             });
         let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: Some("render"),
@@ -351,92 +500,150 @@ impl Renderer {
             fragment: Some(wgpu::FragmentState {
                 module: &copy_shader,
                 entry_point: "fs_main",
-                targets: &[Some(config.format.into())],
+                targets: &[Some(format.into())],
             }),
             multiview: None,
         });
         /* #endregion */
 
         Self {
-            vertex_bind_group_layout,
+            geometry,
+            cull_stats: Cell::new(CullStats::default()),
+            bin_stats: Cell::new(BinStats::default()),
+
+            material_bind_group,
 
             light_buffer,
             light_bind_group,
 
-            g_buffer,
-            g_buffer_bind_group,
-
-            screen_buffer,
-            screen_bind_group,
+            size,
+            buffers,
+            screen_bind_group_layout,
+            bin_bind_group_layout,
 
             camera_buffer,
             camera_bind_group,
 
+            pc,
+            pc_buffer,
+
+            bin_pipeline,
             g_buffer_pipeline,
+            hardware,
             lighting_pipeline,
             render_pipeline,
+            minimap: Minimap::new(device, format),
+        }
+    }
+
+    pub fn resolution(&self) -> (u32, u32) {
+        self.size
+    }
+
+    /// Changes the internal resolution, remaking everything sized by it.
+    pub fn set_resolution(&mut self, device: &wgpu::Device, size: (u32, u32)) {
+        if size == self.size {
+            return;
+        }
+        self.size = size;
+        self.buffers = ScreenBuffers::new(device, size,
+            &self.screen_bind_group_layout, &self.bin_bind_group_layout);
+        if let Some(hardware) = &mut self.hardware {
+            hardware.resize(device, size);
         }
     }
 
+    /// Whether frames have the minimap drawn over them.
+    pub fn minimap_shown(&self) -> bool {
+        self.minimap.shown
+    }
+
+    pub fn show_minimap(&mut self, shown: bool) {
+        self.minimap.shown = shown;
+    }
+
+    pub fn acquire_tile(&mut self, tile: &mut Tile) {
+        self.geometry.acquire(tile);
+    }
+
+    pub fn release_tile(&mut self, tile: &mut Tile) {
+        self.geometry.release(tile);
+    }
+
+    pub fn pool_stats(&self) -> PoolStats {
+        self.geometry.stats()
+    }
+
+    pub fn cull_stats(&self) -> CullStats {
+        self.cull_stats.get()
+    }
+
+    /// What binning did in the latest frame that's made it back from the
+    /// gpu, a frame or so behind. Stays empty with the hardware backend.
+    pub fn bin_stats(&self) -> BinStats {
+        self.bin_stats.get()
+    }
+
+    /// Picks up the bin counters if they're back, warning when a frame
+    /// starts having to leave triangles out.
+    fn collect_bin_stats(&self, device: &wgpu::Device) {
+        let Some(stats) = self.buffers.readback.take(device) else {
+            return;
+        };
+        if stats.overflowed() && !self.bin_stats.get().overflowed() {
+            log::warn!(
+                "binning ran out of room, frames have holes: {:?}", stats);
+        }
+        self.bin_stats.set(stats);
+    }
+
+    /// Draws `scene` into `view`, which has to be in the format the
+    /// renderer was made for.
     pub fn render(&self,
         device: &wgpu::Device,
-        surface: &wgpu::Surface,
         queue: &wgpu::Queue,
-        camera: &Camera,
-        tiles: &mut [Tile],
+        view: &wgpu::TextureView,
+        scene: &Scene,
         borders: &[f32; 2]
-    ) -> Result<(), wgpu::SurfaceError> {
+    ) {
+        let &Scene { camera, player, tiles } = scene;
 
         /* #region SETUP STUFF */
-        let pc = &[(constants::SCREEN_PIXELS.0) as f32,
-            (constants::SCREEN_PIXELS.1) as f32,
+        self.collect_bin_stats(device);
+        let aspect = self.size.0 as f32 / self.size.1 as f32;
+        let pc = &[self.size.0 as f32,
+            self.size.1 as f32,
             borders[0],
             borders[1],
-            constants::WORLD_SCALE,
-            camera.get_position()[2],
+            camera.map_scale(aspect),
+            camera.get_position()[2] as f32,
             constants::NEAR_PLANE,
             constants::FAR_PLANE,
         ];
         let pc_bytes = bytemuck::cast_slice(pc);
 
         queue.write_buffer(&self.camera_buffer, 0,
-            bytemuck::cast_slice(
-                &[camera.world_to_camera(),
-                camera.camera_to_screen(),]
-            ));
-        queue.write_buffer(&self.g_buffer, 0,
-            &[0u8; (constants::SCREEN_PIXELS.0
-                    * constants::SCREEN_PIXELS.1
-                    * 4 * constants::G_BUFFER_NUMS) as usize]
-            );
-        queue.write_buffer(&self.screen_buffer, 0,
-            &[0u8; (constants::SCREEN_PIXELS.0
-                    * constants::SCREEN_PIXELS.1
-                    * 4) as usize]
-            );
+            bytemuck::bytes_of(&camera.uniform(aspect)));
+        if self.pc == PcBinding::Uniform {
+            queue.write_buffer(&self.pc_buffer, 0, pc_bytes);
+        }
 
-        /* SDHFJKJSDKDHSFJKSD */
+        let lights = lights(camera);
         queue.write_buffer(&self.light_buffer, 0,
-            bytemuck::cast_slice(
-                &[camera.get_position()[0],
-                    camera.get_position()[1],
-                    0.1,
-                    camera.get_position()[3],
-                    1.0, 1.0, 1.0, 0.5]
-            ));
-     
-        let output = surface.get_current_texture()?;
-        let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
+            bytemuck::cast_slice(&lights));
 
         let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("Render Encoder"),
         });
+        // the g-buffer needs no clearing, `raster_main` writes every pixel
+        encoder.clear_buffer(&self.buffers.counters_buffer, 0, None);
+        encoder.clear_buffer(&self.buffers.screen_buffer, 0, None);
 
         let render_pass_descriptor = wgpu::RenderPassDescriptor {
             label: None,
             color_attachments: &[
                 Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
+                    view,
                     resolve_target: None,
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Clear(
@@ -456,24 +663,62 @@ impl Renderer {
         /* #endregion */
 
         /* #region COMPUTE PASS */
-        {
-            let mut cpass = 
-                encoder.begin_compute_pass(&ComputePassDescriptor {
-                    label: None,
-                });
-            cpass.set_pipeline(&self.g_buffer_pipeline);
-            cpass.set_bind_group(0, &self.g_buffer_bind_group, &[]);
-            cpass.set_bind_group(1, &self.camera_bind_group, &[]);
-            cpass.set_push_constants(0, pc_bytes);
-            
-            for tile in tiles {
-                match tile.get_vbuf() {
-                    None => {self.make_buffers(tile, device)}
-                    _ => {}
+        let (draws, cull_stats) =
+            self.geometry.update(queue, tiles, &Projection::new(camera, self.size));
+        self.cull_stats.set(cull_stats);
+
+        if let Some(hardware) = &self.hardware {
+            hardware.draw(
+                &mut encoder,
+                &draws,
+                &self.camera_bind_group,
+                &self.material_bind_group,
+                &self.buffers.g_buffer_bind_group,
+                pc_bytes,
+            );
+        } else {
+            {
+                let mut cpass = 
+                    encoder.begin_compute_pass(&ComputePassDescriptor {
+                        label: None,
+                    });
+                cpass.set_pipeline(&self.bin_pipeline);
+                if self.pc == PcBinding::Push {
+                    cpass.set_push_constants(0, pc_bytes);
                 }
-                cpass.set_bind_group(2, tile.get_bind_group().unwrap(), &[]);
-                cpass.dispatch_workgroups((tile.get_size() / 64) + 1, 1, 1);
+                cpass.set_bind_group(0, &self.buffers.bin_bind_group, &[]);
+                cpass.set_bind_group(1, &self.camera_bind_group, &[]);
+                cpass.set_bind_group(3, &self.material_bind_group, &[]);
+
+                for draw in draws.iter() {
+                    cpass.set_bind_group(2, draw.bind_group, &[]);
+                    cpass.dispatch_workgroups(
+                        (draw.index_count / 3).div_ceil(64),
+                        draw.instance_count,
+                        1
+                    );
+                }
+            }
+
+            {
+                let mut cpass = 
+                    encoder.begin_compute_pass(&ComputePassDescriptor {
+                        label: None,
+                    });
+                cpass.set_pipeline(&self.g_buffer_pipeline);
+                if self.pc == PcBinding::Push {
+                    cpass.set_push_constants(0, pc_bytes);
+                }
+                cpass.set_bind_group(0, &self.buffers.g_buffer_bind_group, &[]);
+                cpass.set_bind_group(1, &self.buffers.bin_bind_group, &[]);
+                cpass.set_bind_group(2, &self.camera_bind_group, &[]);
+
+                // one workgroup per bin
+                let bins = raster::bin_grid(self.size);
+                cpass.dispatch_workgroups(bins.0, bins.1, 1);
             }
+            self.buffers.readback.copy(
+                &mut encoder, &self.buffers.counters_buffer);
         }
 
         {
@@ -482,17 +727,19 @@ impl Renderer {
                     label: None,
                 });
             cpass.set_pipeline(&self.lighting_pipeline);
-            cpass.set_push_constants(0, pc_bytes);
+            if self.pc == PcBinding::Push {
+                cpass.set_push_constants(0, pc_bytes);
+            }
 
-            cpass.set_bind_group(0, &self.screen_bind_group, &[]);
-            cpass.set_bind_group(1, &self.g_buffer_bind_group, &[]);
+            cpass.set_bind_group(0, &self.buffers.screen_bind_group, &[]);
+            cpass.set_bind_group(1, &self.buffers.g_buffer_bind_group, &[]);
             cpass.set_bind_group(2, &self.camera_bind_group, &[]);
             cpass.set_bind_group(3, &self.light_bind_group, &[]);
             
             cpass.dispatch_workgroups(
-                (constants::SCREEN_PIXELS.0 + 15) / 16,
-                (constants::SCREEN_PIXELS.1 + 15) / 16,
-                3
+                self.size.0.div_ceil(16),
+                self.size.1.div_ceil(16),
+                1
             );
         }
         /* #endregion */
@@ -501,96 +748,224 @@ impl Renderer {
         {
             let mut rpass = encoder.begin_render_pass(&render_pass_descriptor);
             rpass.set_pipeline(&self.render_pipeline);
-            rpass.set_push_constants(
-                wgpu::ShaderStages::VERTEX_FRAGMENT,
-                0,
-                bytemuck::cast_slice(
-                    &[(constants::SCREEN_PIXELS.0) as f32,
-                    (constants::SCREEN_PIXELS.1) as f32,
-                    borders[0],
-                    borders[1],
-                    (constants::WORLD_SCALE) as f32,
-                    camera.get_position()[2],
-                    constants::NEAR_PLANE,
-                    constants::FAR_PLANE,
-                ]
-            ));
-            rpass.set_bind_group(0, &self.screen_bind_group, &[]);
+            if self.pc == PcBinding::Push {
+                rpass.set_push_constants(
+                    wgpu::ShaderStages::VERTEX_FRAGMENT, 0, pc_bytes);
+            }
+            rpass.set_bind_group(0, &self.buffers.screen_bind_group, &[]);
+            rpass.set_bind_group(1, &self.camera_bind_group, &[]);
             rpass.draw(0..6, 0..1);
+
+            if self.minimap.shown {
+                // the lights that stay put are what there is to find
+                let vertices = minimap::vertices(
+                    player, tiles, &lights[1..], borders, self.size);
+                self.minimap.draw(queue, &mut rpass, &vertices);
+            }
         }
         /* #endregion */
 
         queue.submit(std::iter::once(encoder.finish()));
-        output.present();
-
-        Ok(())
+        self.buffers.readback.map();
     }
+}
 
-    pub fn make_buffers(
-        &self,
-        tile: &mut Tile,
+/// The screen, the G-buffer and the triangle bins, all sized by the
+/// internal resolution.
+struct ScreenBuffers {
+    screen_buffer: wgpu::Buffer,
+    screen_bind_group: wgpu::BindGroup,
+
+    g_buffer_bind_group: wgpu::BindGroup,
+
+    counters_buffer: wgpu::Buffer,
+    bin_bind_group: wgpu::BindGroup,
+    readback: CountersReadback,
+}
+
+impl ScreenBuffers {
+    fn new(
         device: &wgpu::Device,
-    ) {
-        let vbuf = device.create_buffer_init(
-            &wgpu::util::BufferInitDescriptor {
-                label: Some("Vertex Buffer"),
-                contents: bytemuck::cast_slice(tile.get_vertices()),
-                usage: wgpu::BufferUsages::STORAGE,
-            }
-        );
-        let ibuf = device.create_buffer_init(
-            &wgpu::util::BufferInitDescriptor {
-                label: Some("Index Buffer"),
-                contents: bytemuck::cast_slice(tile.get_indices()),
-                usage: wgpu::BufferUsages::STORAGE,
-            }
-        );
-        let pos = device.create_buffer_init(
-            &wgpu::util::BufferInitDescriptor {
-                label: Some("Position Buffer"),
-                contents: bytemuck::cast_slice(tile.get_mat().as_slice()),
-                usage: wgpu::BufferUsages::STORAGE
-                    | wgpu::BufferUsages::COPY_DST,
-            }
-        );
-        let tile_bind_group = device.create_bind_group(
+        size: (u32, u32),
+        screen_bind_group_layout: &wgpu::BindGroupLayout,
+        bin_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> ScreenBuffers {
+        let screen_buffer =
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: None,
+                size: (size.0 * size.1 * 4) as u64,
+                usage: wgpu::BufferUsages::COPY_DST
+                    | wgpu::BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            });
+        let screen_bind_group = device.create_bind_group(
             &wgpu::BindGroupDescriptor {
-                label: Some("vbuf_bind_group"),
-                layout: &self.vertex_bind_group_layout,
+                label: Some("compute output"),
+                layout: screen_bind_group_layout,
                 entries: &[
                     wgpu::BindGroupEntry {
                         binding: 0,
                         resource: wgpu::BindingResource::Buffer(
                             wgpu::BufferBinding {
-                                buffer: &vbuf,
+                                buffer: &screen_buffer,
                                 offset: 0,
-                                size: None,
-                            })
+                                size: None
+                            }
+                        ),
                     },
+                ],
+            });
+
+        let g_buffer =
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: None,
+                size: gbuffer::g_buffer_size(size),
+                usage: wgpu::BufferUsages::COPY_DST
+                    | wgpu::BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            });
+        let g_buffer_bind_group = device.create_bind_group(
+            &wgpu::BindGroupDescriptor {
+                label: Some("g-buffer"),
+                layout: screen_bind_group_layout,
+                entries: &[
                     wgpu::BindGroupEntry {
-                        binding: 1,
+                        binding: 0,
                         resource: wgpu::BindingResource::Buffer(
                             wgpu::BufferBinding {
-                                buffer: &ibuf,
+                                buffer: &g_buffer,
                                 offset: 0,
-                                size: None,
-                            })
+                                size: None
+                            }
+                        ),
+                    },
+                ],
+            });
+
+        let screen_triangles_buffer =
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Screen Triangles"),
+                size: raster::SCREEN_TRIANGLES_SIZE,
+                usage: wgpu::BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            });
+        let counters_buffer =
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Bin Counters"),
+                size: raster::counters_size(size),
+                usage: wgpu::BufferUsages::COPY_DST
+                    | wgpu::BufferUsages::COPY_SRC
+                    | wgpu::BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            });
+        let bin_tris_buffer =
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Bin Triangles"),
+                size: raster::bin_tris_size(size),
+                usage: wgpu::BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            });
+        let bin_bind_group = device.create_bind_group(
+            &wgpu::BindGroupDescriptor {
+                label: Some("bins"),
+                layout: bin_bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: screen_triangles_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: counters_buffer.as_entire_binding(),
                     },
                     wgpu::BindGroupEntry {
                         binding: 2,
-                        resource: wgpu::BindingResource::Buffer(
-                            wgpu::BufferBinding {
-                                buffer: &pos,
-                                offset: 0,
-                                size: None,
-                            })
+                        resource: bin_tris_buffer.as_entire_binding(),
                     },
                 ],
             });
-        tile.set_vbuf(vbuf);
-        tile.set_ibuf(ibuf);
-        tile.set_pos(pos);
-        tile.set_bind_group(tile_bind_group);
+
+        Self {
+            screen_buffer,
+            screen_bind_group,
+
+            g_buffer_bind_group,
+
+            readback: CountersReadback::new(device, size),
+            counters_buffer,
+            bin_bind_group,
+        }
     }
+}
+
+// where `CountersReadback` is at with its copy
+const IDLE: u8 = 0;
+const COPIED: u8 = 1;
+const MAPPING: u8 = 2;
+const MAPPED: u8 = 3;
+
+/// The bin counters on their way back to the cpu. Frames don't wait for
+/// them, they're picked up whenever they get there, and no new copy is
+/// made until then.
+struct CountersReadback {
+    buffer: wgpu::Buffer,
+    bytes: u64,
+    state: Arc<AtomicU8>,
+}
 
-}
\ No newline at end of file
+impl CountersReadback {
+    fn new(device: &wgpu::Device, size: (u32, u32)) -> CountersReadback {
+        let bytes = raster::counters_size(size);
+        Self {
+            buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Bin Counters Readback"),
+                size: bytes,
+                usage: wgpu::BufferUsages::COPY_DST
+                    | wgpu::BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            }),
+            bytes,
+            state: Arc::new(AtomicU8::new(IDLE)),
+        }
+    }
+
+    /// Copies `counters` over at the end of `encoder`, unless the last
+    /// copy hasn't been read yet.
+    fn copy(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        counters: &wgpu::Buffer,
+    ) {
+        if self.state.load(Ordering::Acquire) == IDLE {
+            encoder.copy_buffer_to_buffer(
+                counters, 0, &self.buffer, 0, self.bytes);
+            self.state.store(COPIED, Ordering::Release);
+        }
+    }
+
+    /// Starts mapping the copy, once the encoder it's in is submitted.
+    fn map(&self) {
+        if self.state.load(Ordering::Acquire) != COPIED {
+            return;
+        }
+        self.state.store(MAPPING, Ordering::Release);
+        let state = self.state.clone();
+        self.buffer.slice(..).map_async(wgpu::MapMode::Read, move |result|
+            // a failed map just means no stats for that frame
+            state.store(if result.is_ok() { MAPPED } else { IDLE },
+                Ordering::Release));
+    }
+
+    /// The stats in the copy, if it's back.
+    fn take(&self, device: &wgpu::Device) -> Option<BinStats> {
+        device.poll(wgpu::Maintain::Poll);
+        if self.state.load(Ordering::Acquire) != MAPPED {
+            return None;
+        }
+        let stats = BinStats::from_counters(bytemuck::cast_slice(
+            &self.buffer.slice(..).get_mapped_range()));
+        self.buffer.unmap();
+        self.state.store(IDLE, Ordering::Release);
+        Some(stats)
+    }
+}
@@ -0,0 +1,146 @@
+use crate::game::{ camera, material };
+use super::{ gbuffer, geometry, raster, shadow };
+
+// Every shader is its hand-written source in `shaders/` with whatever
+// generated declarations it needs stuck on the front. Naga wants things
+// declared before they're used, so the push constants go first.
+
+/// Where shaders read `pc` from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PcBinding {
+    /// `var<push_constant>`, needs `Features::PUSH_CONSTANTS`
+    Push,
+    /// a uniform buffer at binding 1 of the camera's bind group, for
+    /// adapters without push constants
+    Uniform,
+}
+
+impl PcBinding {
+    /// Push constant ranges for a pipeline layout whose shaders read `pc`
+    /// in `stages`.
+    pub fn ranges(self, stages: wgpu::ShaderStages)
+        -> Vec<wgpu::PushConstantRange>
+    {
+        match self {
+            PcBinding::Push => vec![wgpu::PushConstantRange {
+                stages,
+                range: 0..PUSH_CONSTANTS_SIZE,
+            }],
+            PcBinding::Uniform => Vec::new(),
+        }
+    }
+}
+
+/// Bytes in `PushConstants`.
+pub const PUSH_CONSTANTS_SIZE: u32 = 32;
+
+const PUSH_CONSTANTS: &str = "
+struct PushConstants {
+    pix_x: f32,
+    pix_y: f32,
+    border_x: f32,
+    border_y: f32,
+    map_scale: f32,
+    cam_z: f32,
+    near_plane: f32,
+    far_plane: f32,
+};
+";
+
+/// The `PushConstants` struct and `pc` itself. `camera_group` is the bind
+/// group the shader's pipeline has the camera in.
+fn push_constants(pc: PcBinding, camera_group: u32) -> String {
+    PUSH_CONSTANTS.to_owned() + &match pc {
+        PcBinding::Push => "var<push_constant> pc: PushConstants;\n"
+            .to_owned(),
+        PcBinding::Uniform => format!(
+            "@group({}) @binding(1) var<uniform> pc: PushConstants;\n",
+            camera_group,
+        ),
+    }
+}
+
+pub fn binning(pc: PcBinding) -> String {
+    push_constants(pc, 1)
+        + &gbuffer::wgsl_prelude()
+        + &material::wgsl_material()
+        + &raster::wgsl_prelude()
+        + &camera::wgsl_camera()
+        + &geometry::wgsl_instances()
+        + include_str!("shaders/binning.wgsl")
+}
+
+pub fn g_buffer(pc: PcBinding) -> String {
+    push_constants(pc, 2)
+        + &gbuffer::wgsl_prelude()
+        + &raster::wgsl_prelude()
+        + include_str!("shaders/g_buffer.wgsl")
+}
+
+pub fn hardware(pc: PcBinding) -> String {
+    push_constants(pc, 0)
+        + &gbuffer::wgsl_prelude()
+        + &gbuffer::wgsl_targets()
+        + &material::wgsl_material()
+        + &camera::wgsl_camera()
+        + &geometry::wgsl_instances()
+        + include_str!("shaders/hardware.wgsl")
+}
+
+pub fn pack(pc: PcBinding) -> String {
+    push_constants(pc, 2)
+        + &gbuffer::wgsl_prelude()
+        + include_str!("shaders/pack.wgsl")
+        + &gbuffer::wgsl_pack()
+}
+
+pub fn lighting(pc: PcBinding) -> String {
+    push_constants(pc, 2)
+        + &gbuffer::wgsl_prelude()
+        + &material::wgsl_material()
+        + &shadow::wgsl_constants()
+        + &camera::wgsl_camera()
+        + include_str!("shaders/lighting.wgsl")
+}
+
+pub fn copy(pc: PcBinding) -> String {
+    push_constants(pc, 1) + include_str!("shaders/copy.wgsl")
+}
+
+pub fn minimap() -> String {
+    include_str!("shaders/minimap.wgsl").to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PcBinding;
+    use naga::valid::Capabilities;
+
+    fn validate(name: &str, source: &str, pc: PcBinding) {
+        let module = naga::front::wgsl::parse_str(source)
+            .unwrap_or_else(|e| panic!("{}: {}", name, e.emit_to_string(source)));
+        let capabilities = match pc {
+            PcBinding::Push => Capabilities::all(),
+            PcBinding::Uniform => Capabilities::all()
+                - Capabilities::PUSH_CONSTANT,
+        };
+        naga::valid::Validator::new(
+            naga::valid::ValidationFlags::all(),
+            capabilities,
+        ).validate(&module)
+            .unwrap_or_else(|e| panic!("{}: {:?}", name, e));
+    }
+
+    #[test]
+    fn shaders_validate() {
+        for pc in [PcBinding::Push, PcBinding::Uniform] {
+            validate("binning", &super::binning(pc), pc);
+            validate("g_buffer", &super::g_buffer(pc), pc);
+            validate("hardware", &super::hardware(pc), pc);
+            validate("pack", &super::pack(pc), pc);
+            validate("lighting", &super::lighting(pc), pc);
+            validate("copy", &super::copy(pc), pc);
+            validate("minimap", &super::minimap(), pc);
+        }
+    }
+}
@@ -0,0 +1,238 @@
+struct Vertex {
+    pos: vec4<f32>,
+    color: vec4<f32>
+};
+
+@group(0) @binding(0) var<storage, read_write> triangles: ScreenTriangles;
+@group(0) @binding(1) var<storage, read_write> counters: Counters;
+@group(0) @binding(2) var<storage, read_write> bin_tris: array<u32>;
+@group(1) @binding(0) var<uniform> cam: Camera;
+@group(2) @binding(0) var<storage, read> vertices : array<Vertex>;
+@group(2) @binding(1) var<storage, read> indices : array<u32>;
+@group(2) @binding(2) var<storage, read> transforms : array<mat4x4<f32>>;
+@group(2) @binding(3) var<storage, read> tri_materials : array<u32>;
+@group(2) @binding(4) var<uniform> instances : MeshInstances;
+@group(3) @binding(0) var<storage, read> materials : array<Material>;
+
+fn triangle_normal(a: vec3<f32>, b: vec3<f32>, c: vec3<f32>) -> vec3<f32> {
+    // computes the normal of a triangle //
+    return normalize(cross((b - c), (a - c)));
+}
+
+fn w_to_c(tile: mat4x4<f32>, v: vec4<f32>) -> vec3<f32> {
+    // translates from tile coords to camera coords //
+    let a = cam.world_to_cam * tile * v;
+    let m = to_model(cam.projection, a);
+    let b = vec4(m, a.z - pc.cam_z, 1.0) * pc.map_scale;
+    let c = (cam.cam_to_screen * b).xyz * pc.pix_x
+        + vec3(pc.pix_x / 2., pc.pix_y / 2., 0.0);
+    return c;
+}
+
+fn w_to_eye(tile: mat4x4<f32>, v: vec4<f32>) -> vec3<f32> {
+    // translates from tile coords to the first-person eye's //
+    return to_eye(cam.cam_to_screen, cam.world_to_cam * tile * v);
+}
+
+struct ClipVertex {
+    pos: vec3<f32>,
+    color: vec4<f32>,
+};
+
+// each of the seven planes adds at most one corner to the triangle //
+var<private> poly: array<ClipVertex, 10>;
+var<private> poly_len: u32;
+
+fn clip_plane(axis: u32, side: f32, bound: f32) {
+    // keeps the part of `poly` where side * (pos[axis] - bound) >= 0 //
+    var out: array<ClipVertex, 10>;
+    var out_len = 0u;
+    for (var i: u32 = 0u; i < poly_len; i = i + 1u) {
+        let cur = poly[i];
+        let next = poly[(i + 1u) % poly_len];
+        let d_cur = side * (cur.pos[axis] - bound);
+        let d_next = side * (next.pos[axis] - bound);
+        if d_cur >= 0.0 {
+            out[out_len] = cur;
+            out_len = out_len + 1u;
+        }
+        if (d_cur >= 0.0) != (d_next >= 0.0) {
+            // always measured from the inside corner, so a neighbour //
+            // walking the edge the other way gets the same point     //
+            var inside = cur;
+            var outside = next;
+            if d_cur < 0.0 {
+                inside = next;
+                outside = cur;
+            }
+            let d_in = side * (inside.pos[axis] - bound);
+            let d_out = side * (outside.pos[axis] - bound);
+            let t = d_in / (d_in - d_out);
+            out[out_len] = ClipVertex(
+                mix(inside.pos, outside.pos, t),
+                mix(inside.color, outside.color, t)
+            );
+            out_len = out_len + 1u;
+        }
+    }
+    poly = out;
+    poly_len = out_len;
+}
+
+fn bin_triangle(
+    a: ClipVertex,
+    b: ClipVertex,
+    c: ClipVertex,
+    normal: u32,
+    emissive: u32,
+    mat_id: u32,
+) {
+    // the edge functions are exact in i32 as long as the triangle stays //
+    // within MAX_TRIANGLE_EXTENT, which clipping to the guard band      //
+    // already makes sure of                                             //
+    let sa = snap(a.pos);
+    var vb = b;
+    var vc = c;
+    var sb = snap(vb.pos);
+    var sc = snap(vc.pos);
+    var area = edge(sa, sb, sc);
+    if area == 0 {
+        return;
+    }
+    if area < 0 {
+        let s = sb; sb = sc; sc = s;
+        let v = vb; vb = vc; vc = v;
+        area = -area;
+    }
+
+    let min_s = min(min(sa, sb), sc);
+    let max_s = max(max(sa, sb), sc);
+    if max_s.x - min_s.x > MAX_TRIANGLE_EXTENT
+    || max_s.y - min_s.y > MAX_TRIANGLE_EXTENT {
+        return;
+    }
+
+    // first and last pixel centers inside the bounding box //
+    let half = SUBPIXEL_SCALE / 2;
+    let scale = f32(SUBPIXEL_SCALE);
+    let start = max(
+        vec2<i32>(ceil(vec2<f32>(min_s - half) / scale)),
+        vec2(0)
+    );
+    let end = min(
+        vec2<i32>(floor(vec2<f32>(max_s - half) / scale)),
+        vec2(i32(pc.pix_x) - 1, i32(pc.pix_y) - 1)
+    );
+    if start.x > end.x || start.y > end.y {
+        return;
+    }
+
+    let slot = atomicAdd(&counters.values[0], 1u);
+    if slot >= MAX_SCREEN_TRIANGLES {
+        return;
+    }
+
+    var tri: ScreenTriangle;
+    tri.a = sa;
+    tri.b = sb;
+    tri.c = sc;
+    tri.area = area;
+    tri.normal = normal;
+    tri.z = vec4(a.pos.z, vb.pos.z, vc.pos.z, 0.0);
+    tri.colors = vec4(
+        color_to_int(a.color),
+        color_to_int(vb.color),
+        color_to_int(vc.color),
+        emissive
+    );
+    tri.material = mat_id;
+    triangles.values[slot] = tri;
+
+    let first_bin = vec2<u32>(start) / BIN_SIZE;
+    let last_bin = vec2<u32>(end) / BIN_SIZE;
+    for (var by: u32 = first_bin.y; by <= last_bin.y; by = by + 1u) {
+        for (var bx: u32 = first_bin.x; bx <= last_bin.x; bx = bx + 1u) {
+            let bin = bx + by * bins_x();
+            let n = atomicAdd(&counters.values[1u + bin], 1u);
+            if n < BIN_CAPACITY {
+                bin_tris[bin * BIN_CAPACITY + n] = slot;
+            }
+        }
+    }
+}
+
+// Drawing happens in two steps. This runs once per triangle: it projects //
+// and clips it, stores what's left, and adds it to the list of every     //
+// screen bin its bounding box touches. `raster_main` in g_buffer.wgsl    //
+// then runs one thread per pixel, walking only its bin's list, so one    //
+// huge triangle costs a list entry per bin instead of a long loop on a   //
+// single thread. x is the triangle and y the tile drawn with this mesh. //
+@compute
+@workgroup_size(64)
+fn bin_main(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
+    let id = global_invocation_id.x;
+    let index = id * 3u;
+    if (index >= arrayLength(&indices)) {
+        return;
+    }
+    let i = global_invocation_id.y;
+    let tile = transforms[instances.first + instances.slots[i / 4u][i % 4u]];
+
+    let va = vertices[indices[index + 0u]];
+    let vb = vertices[indices[index + 1u]];
+    let vc = vertices[indices[index + 2u]];
+    let in_eye_space = cam.projection == PROJECTION_FIRST_PERSON;
+    var a: vec3<f32>;
+    var b: vec3<f32>;
+    var c: vec3<f32>;
+    var norm: vec3<f32>;
+    if in_eye_space {
+        a = w_to_eye(tile, va.pos);
+        b = w_to_eye(tile, vb.pos);
+        c = w_to_eye(tile, vc.pos);
+        norm = first_person_normal(a, b, c);
+    } else {
+        a = w_to_c(tile, va.pos);
+        b = w_to_c(tile, vb.pos);
+        c = w_to_c(tile, vc.pos);
+        norm = triangle_normal(a, b, c);
+    }
+    if norm.z < 0.0 {
+        return;
+    }
+
+    let mat_id = tri_materials[id];
+    let surface = materials[mat_id];
+    poly[0] = ClipVertex(a, va.color * surface.diffuse);
+    poly[1] = ClipVertex(b, vb.color * surface.diffuse);
+    poly[2] = ClipVertex(c, vc.color * surface.diffuse);
+    poly_len = 3u;
+
+    // First person is cut at the near plane while still in eye space, //
+    // since the perspective divide wraps anything behind the eye      //
+    // around, and only then projected.                                //
+    if in_eye_space {
+        clip_plane(2u, 1.0, NEAR_DISTANCE);
+        for (var i: u32 = 0u; i < poly_len; i = i + 1u) {
+            poly[i].pos = first_person(poly[i].pos);
+        }
+    }
+
+    // The sides are clipped to a band around the screen rather than the //
+    // screen itself. The cuts then land where no pixel center can see   //
+    // them, and the new corners' rounding can't open cracks between     //
+    // neighbours. Depth is affine in screen space, so it clips exactly. //
+    let guard = f32(GUARD_BAND);
+    clip_plane(0u, 1.0, -guard);
+    clip_plane(0u, -1.0, pc.pix_x + guard);
+    clip_plane(1u, 1.0, -guard);
+    clip_plane(1u, -1.0, pc.pix_y + guard);
+    clip_plane(2u, 1.0, pc.far_plane);
+    clip_plane(2u, -1.0, pc.near_plane);
+
+    let normal = normal_to_int(norm);
+    let emissive = color_to_int(surface.emissive);
+    for (var i: u32 = 2u; i < poly_len; i = i + 1u) {
+        bin_triangle(poly[0], poly[i - 1u], poly[i], normal, emissive, mat_id);
+    }
+}
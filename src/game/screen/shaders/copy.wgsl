@@ -7,18 +7,6 @@ struct VertexOutput {
     @builtin(position) position: vec4<f32>,
 };
 
-struct PushConstants {
-    pix_x: f32,
-    pix_y: f32,
-    border_x: f32,
-    border_y: f32,
-    map_scale: f32,
-    cam_z: f32,
-    near_plane: f32,
-    far_plane: f32,
-};
-
-var<push_constant> pc: PushConstants;
 
 @vertex
 fn vs_main(@builtin(vertex_index) VertexIndex : u32) -> VertexOutput {
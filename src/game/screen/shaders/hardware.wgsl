@@ -0,0 +1,121 @@
+struct Vertex {
+    pos: vec4<f32>,
+    color: vec4<f32>
+};
+
+struct Fragment {
+    @builtin(position) position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+    @location(1) @interpolate(flat) normal: u32,
+    @location(2) @interpolate(flat) material: u32,
+    @location(3) @interpolate(flat) emissive: u32,
+};
+
+@group(0) @binding(0) var<uniform> cam: Camera;
+@group(1) @binding(0) var<storage, read> vertices : array<Vertex>;
+@group(1) @binding(1) var<storage, read> indices : array<u32>;
+@group(1) @binding(2) var<storage, read> transforms : array<mat4x4<f32>>;
+@group(1) @binding(3) var<storage, read> tri_materials : array<u32>;
+@group(1) @binding(4) var<uniform> instances : MeshInstances;
+@group(2) @binding(0) var<storage, read> materials : array<Material>;
+
+fn triangle_normal(a: vec3<f32>, b: vec3<f32>, c: vec3<f32>) -> vec3<f32> {
+    // computes the normal of a triangle //
+    return normalize(cross((b - c), (a - c)));
+}
+
+fn w_to_c(tile: mat4x4<f32>, v: vec4<f32>) -> vec3<f32> {
+    // translates from tile coords to camera coords //
+    let a = cam.world_to_cam * tile * v;
+    let m = to_model(cam.projection, a);
+    let b = vec4(m, a.z - pc.cam_z, 1.0) * pc.map_scale;
+    let c = (cam.cam_to_screen * b).xyz * pc.pix_x
+        + vec3(pc.pix_x / 2., pc.pix_y / 2., 0.0);
+    return c;
+}
+
+fn w_to_eye(tile: mat4x4<f32>, v: vec4<f32>) -> vec3<f32> {
+    // translates from tile coords to the first-person eye's //
+    return to_eye(cam.cam_to_screen, cam.world_to_cam * tile * v);
+}
+
+// Draws the same triangles as `bin_main`, but lets the gpu's own    //
+// rasterizer and depth test do the work. Vertices are pulled out of //
+// the tile's storage buffers by index, so each one can see its      //
+// whole triangle for the flat normal and the backface test. Each   //
+// instance is one on-screen tile drawn with this mesh.              //
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
+    @builtin(instance_index) instance_index: u32,
+) -> Fragment {
+    let i = instance_index;
+    let tile = transforms[instances.first + instances.slots[i / 4u][i % 4u]];
+    var out: Fragment;
+
+    let first = vertex_index - vertex_index % 3u;
+    let vertex = vertices[indices[vertex_index]];
+    var norm: vec3<f32>;
+    if cam.projection == PROJECTION_FIRST_PERSON {
+        let a = w_to_eye(tile, vertices[indices[first + 0u]].pos);
+        let b = w_to_eye(tile, vertices[indices[first + 1u]].pos);
+        let c = w_to_eye(tile, vertices[indices[first + 2u]].pos);
+        let e = w_to_eye(tile, vertex.pos);
+        norm = first_person_normal(a, b, c);
+
+        // left homogeneous, so the near plane is clipped before the //
+        // perspective divide. Divides out to the same as below.     //
+        out.position = vec4(
+            FOCAL_LENGTH * e.x,
+            FOCAL_LENGTH * e.y * pc.pix_x / pc.pix_y,
+            (e.z - NEAR_DISTANCE) * pc.near_plane
+                / (pc.near_plane - pc.far_plane),
+            e.z
+        );
+    } else {
+        let a = w_to_c(tile, vertices[indices[first + 0u]].pos);
+        let b = w_to_c(tile, vertices[indices[first + 1u]].pos);
+        let c = w_to_c(tile, vertices[indices[first + 2u]].pos);
+        let p = w_to_c(tile, vertex.pos);
+        norm = triangle_normal(a, b, c);
+
+        // pixel space with y down, to clip space with y up. The compute //
+        // backend discards outside the same depth range.                //
+        out.position = vec4(
+            p.x / pc.pix_x * 2.0 - 1.0,
+            1.0 - p.y / pc.pix_y * 2.0,
+            (pc.near_plane - p.z) / (pc.near_plane - pc.far_plane),
+            1.0
+        );
+    }
+
+    if norm.z < 0.0 {
+        // every corner lands outside the clip volume, so it's dropped //
+        out.position = vec4(2.0, 2.0, 2.0, 1.0);
+        return out;
+    }
+
+    let mat_id = tri_materials[vertex_index / 3u];
+    let surface = materials[mat_id];
+
+    // rounded to 8 bits first, like the compute backend's vertices //
+    out.color = int_to_color(color_to_int(vertex.color * surface.diffuse));
+    out.normal = normal_to_int(norm);
+    out.material = mat_id;
+    out.emissive = color_to_int(surface.emissive);
+    return out;
+}
+
+@fragment
+fn fs_main(in: Fragment) -> GBufferTargets {
+    var out: GBufferTargets;
+    // depth is affine in screen space, same as the compute backend //
+    // interpolates it, so it's read back out of the position        //
+    let z = pc.near_plane - in.position.z * (pc.near_plane - pc.far_plane);
+    out.depth = z_to_depth_buf(z);
+    out.color = color_to_int(in.color);
+    out.normal = in.normal;
+    out.material = in.material;
+    out.emissive = in.emissive;
+    return out;
+}
@@ -11,71 +11,82 @@ struct Screen {
     values: array<atomic<u32>>
 };
 
-struct PushConstants {
-    pix_x: f32,
-    pix_y: f32,
-    border_x: f32,
-    border_y: f32,
-    map_scale: f32,
-    cam_z: f32,
-    near_plane: f32,
-    far_plane: f32,
-};
-
-struct Camera {
-    world_to_cam: mat4x4<f32>,
-    cam_to_screen: mat4x4<f32>,
-};
-
-var<push_constant> pc: PushConstants;
 @group(0) @binding(0) var<storage, read_write> screen : Screen;
 @group(1) @binding(0) var<storage, read_write> g_buffer : GBuffer;
 @group(2) @binding(0) var<uniform> cam : Camera;
 @group(3) @binding(0) var<storage, read> light_buf : array<Light>;
+@group(3) @binding(1) var<storage, read> materials : array<Material>;
 
 fn w_to_c(v: vec4<f32>) -> vec3<f32> {
     // translates from world coords to camera coords //
-    let a = cam.world_to_cam * position * v;
-    let b = vec4(a.xy, a.z - pc.cam_z, 1.0) * pc.map_scale;
+    let a = cam.world_to_cam * v;
+    if cam.projection == PROJECTION_FIRST_PERSON {
+        return first_person(to_eye(cam.cam_to_screen, a));
+    }
+    let m = to_model(cam.projection, a);
+    let b = vec4(m, a.z - pc.cam_z, 1.0) * pc.map_scale;
     let c = (cam.cam_to_screen * b).xyz * pc.pix_x
         + vec3(pc.pix_x / 2., pc.pix_y / 2., 0.0);
     return c;
 }
 
-fn depth_buf_to_z(num: u32) -> f32 {
-    return f32(num) / 16777215.0
-        * (pc.near_plane - pc.far_plane)
-        + pc.far_plane;
-}
+fn shadow(p: vec3<f32>, l_pos: vec3<f32>) -> f32 {
+    // marches from p towards the light through the depth buffer, //
+    // returning 0.0 if something in front of the ray blocks it   //
+    let ray = l_pos - p;
+    for (var i: u32 = 1u; i < SHADOW_STEPS; i = i + 1u) {
+        let s = p + ray * (f32(i) / f32(SHADOW_STEPS));
+        if s.x < 0.0 || s.y < 0.0 || s.x >= pc.pix_x || s.y >= pc.pix_y {
+            break;
+        }
 
-// functions for indexing the g-buffer //
-fn depth(x: u32, y: u32) -> u32 { return (x + y * u32(pc.pix_x)) * 3u; }
-fn color(x: u32, y: u32) -> u32 { return (x + y * u32(pc.pix_x)) * 3u + 1u; }
-fn normal(x: u32, y: u32) -> u32 { return (x + y * u32(pc.pix_x)) * 3u + 2u; }
+        let stored = atomicLoad(
+            &g_buffer.values[depth(u32(s.x), u32(s.y))]
+        );
+        if stored == 0u {
+            continue;
+        }
 
-fn int_to_normal(num: u32) -> vec3<f32> {
-    let x = (f32(num >> 16u) / 65535.0) * 2.0 - 1.0;
-    let y = (f32(num % (1u << 16u)) / 65535.0) * 2.0 - 1.0;
-    let z = sqrt(1.0 - x * x - y * y);
-    return vec3<f32>(x, y, z);
+        let diff = depth_buf_to_z(stored) - s.z;
+        if diff > SHADOW_BIAS && diff < SHADOW_THICKNESS {
+            return 0.0;
+        }
+    }
+    return 1.0;
 }
 
-fn color_to_int(col: vec4<f32>) -> u32 {
-    // takes a color vector and converts it to an integer //
-    return u32(255.0 * clamp(col.a, 0.0, 1.0))
-        + (u32(255.0 * clamp(col.b, 0.0, 1.0)) << 8u)
-        + (u32(255.0 * clamp(col.g, 0.0, 1.0)) << 16u)
-        + (u32(255.0 * clamp(col.r, 0.0, 1.0)) << 24u);
-}
 
-fn int_to_color(in: u32) -> vec4<f32> {
-    // takes an integer and converts it to a color vector //
-    return vec4<f32>(
-        f32(in >> 24u) / 255.0,
-        f32((in >> 16u) % 256u) / 255.0,
-        f32((in >> 8u) % 256u) / 255.0,
-        f32(in % 256u) / 255.0,
+fn lit_by(
+    l: Light, p: vec3<f32>, norm: vec3<f32>, pcol: vec4<f32>,
+    surface: Material
+) -> vec4<f32> {
+    // what the light `l` adds to the surface at `p` //
+    if l.pos.w < 0.0 {
+        return vec4(0.0);
+    }
+    let l_pos = w_to_c(l.pos);
+    if l_pos.z >= BEHIND_CAMERA {
+        return vec4(0.0);
+    }
+    let ray = (p - l_pos) / (pc.map_scale * pc.pix_x);
+    if dot(ray, ray) == 0.0 {
+        return vec4(0.0);
+    }
+    let brightness = l.color.a / (dot(ray, ray) * 100.0)
+        * shadow(p, l_pos);
+
+    // lambert plus blinn-phong, with the viewer looking down -z //
+    let to_light = -normalize(ray);
+    let diffuse = clamp(dot(to_light, norm), 0.0, 1.0);
+    let halfway = normalize(to_light + vec3(0.0, 0.0, 1.0));
+    let specular = select(
+        0.0,
+        surface.specular * pow(max(dot(norm, halfway), 0.0), surface.exponent),
+        diffuse > 0.0
     );
+
+    return brightness * vec4(l.color.rgb, 1.0)
+        * (pcol * diffuse + vec4(vec3(specular), 0.0));
 }
 
 @compute
@@ -83,71 +94,36 @@ fn int_to_color(in: u32) -> vec4<f32> {
 fn main(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
     let x = global_invocation_id.x;
     let y = global_invocation_id.y;
-    let light = global_invocation_id.z;
 
-    if (x >= u32(pc.pix_x))
-    || (y >= u32(pc.pix_y))
-    || (light > arrayLength(&light_buf)) {
+    if (x >= u32(pc.pix_x)) || (y >= u32(pc.pix_y)) {
         return;
     }
 
     let idx = x + y * u32(pc.pix_x);
+    let surface = materials[atomicLoad(&g_buffer.values[material(x, y)])];
+    let unlit = (surface.flags & MATERIAL_UNLIT) != 0u;
 
-    if light == 0u {
-        let col = int_to_color(
-            atomicLoad(&g_buffer.values[color(x, y)])
-        );
-        atomicStore(
-            &screen.values[idx],
-            color_to_int(
-                vec4(0.05 * col.rgb, col.a)
-                + int_to_color(
-                    atomicLoad(&screen.values[idx])
-                )
-            )
-        )
-    } else {
-        let lidx = u32(i32(light) - 1);
-        if light_buf[lidx].pos.w < 0.0 {
-            return;
-        }
-        let l_pos = w_to_c(light_buf[lidx].pos);
-        let col = light_buf[lidx].color;
-        let p = vec3<f32>(
-            f32(x),
-            f32(y),
-            depth_buf_to_z(
-                atomicLoad(
-                    &g_buffer.values[depth(x, y)]
-                )
-            )
-        );
-
-        let pcol = int_to_color(atomicLoad(
-            &g_buffer.values[color(x, y)]
-        ));
-
+    let col = int_to_color(
+        atomicLoad(&g_buffer.values[color(x, y)])
+    );
+    let glow = int_to_color(
+        atomicLoad(&g_buffer.values[emissive(x, y)])
+    );
+    let ambient = select(0.05, 1.0, unlit);
+    var total = vec4(ambient * col.rgb + glow.a * glow.rgb, col.a);
+
+    // every light is added up here, in this pixel's one invocation, //
+    // so nothing else writes to it meanwhile                        //
+    let stored_depth = atomicLoad(&g_buffer.values[depth(x, y)]);
+    if stored_depth != 0u && !unlit {
+        let p = vec3<f32>(f32(x), f32(y), depth_buf_to_z(stored_depth));
         let norm = int_to_normal(
-            atomicLoad(
-                &g_buffer.values[normal(x, y)]
-            )
+            atomicLoad(&g_buffer.values[normal(x, y)])
         );
-
-        let ray = (p - l_pos) / (pc.map_scale * pc.pix_x);
-        if dot(ray, ray) == 0.0 {
-            return;
+        for (var i: u32 = 0u; i < arrayLength(&light_buf); i = i + 1u) {
+            total = total + lit_by(light_buf[i], p, norm, col, surface);
         }
-        let brightness = col.a / (dot(ray, ray) * 100.0);
-
-        atomicStore(
-            &screen.values[idx],
-            color_to_int(
-                brightness * vec4(col.rgb, 1.0) * pcol
-                * clamp(-dot(normalize(ray), norm), 0.0, 1.0)
-                + int_to_color(
-                    atomicLoad(&screen.values[idx])
-                )
-            )
-        )
     }
-}
\ No newline at end of file
+
+    atomicStore(&screen.values[idx], color_to_int(total));
+}
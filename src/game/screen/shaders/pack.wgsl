@@ -0,0 +1,9 @@
+// The hardware backend leaves each channel in its own render target. //
+// `pack_main`, generated from the layout in gbuffer.rs and appended  //
+// after this, copies them into the same buffer the compute backend   //
+// writes, so lighting can't tell which one ran.                      //
+struct GBuffer {
+    values: array<u32>
+};
+
+@group(0) @binding(0) var<storage, read_write> g_buffer: GBuffer;
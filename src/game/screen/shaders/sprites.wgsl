@@ -29,25 +29,6 @@ var<push_constant> pc: PushConstants;
 @group(2) @binding(0) var tex: texture_2d<f32>;
 @group(2) @binding(1) var samp: sampler;
 
-// functions for indexing the g-buffer //
-fn depth(x: u32, y: u32) -> u32 { return (x + y * u32(pc.pix_x)) * 3u; }
-fn color(x: u32, y: u32) -> u32 { return (x + y * u32(pc.pix_x)) * 3u + 1u; }
-fn normal(x: u32, y: u32) -> u32 { return (x + y * u32(pc.pix_x)) * 3u + 2u; }
-
-fn color_to_int(col: vec4<f32>) -> u32 {
-    // takes a color vector and converts it to an integer //
-    return u32(255.0 * clamp(col.a, 0.0, 1.0))
-        + (u32(255.0 * clamp(col.b, 0.0, 1.0)) << 8u)
-        + (u32(255.0 * clamp(col.g, 0.0, 1.0)) << 16u)
-        + (u32(255.0 * clamp(col.r, 0.0, 1.0)) << 24u);
-}
-
-fn normal_to_int(norm: vec3<f32>) -> u32 {
-    // takes a normal and converts it to an integer //
-    return (u32(65535.0 * clamp((norm.x + 1.0) / 2.0, 0.0, 1.0)) << 16u)
-        + u32(65535.0 * clamp((norm.y + 1.0) / 2.0, 0.0, 1.0));
-}
-
 fn w_to_c(v: vec4<f32>) -> vec3<f32> {
     // translates from world coords to camera coords //
     let a = cam.world_to_cam * position * v;
@@ -0,0 +1,141 @@
+use crate::constants;
+
+/// Declarations for the shadow constants, prepended to `lighting.wgsl`
+/// so the shader and the Rust side can't drift apart.
+pub fn wgsl_constants() -> String {
+    format!(
+        "let SHADOW_STEPS: u32 = {}u;\n\
+        let SHADOW_BIAS: f32 = {:?};\n\
+        let SHADOW_THICKNESS: f32 = {:?};\n",
+        constants::SHADOW_STEPS,
+        constants::SHADOW_BIAS,
+        constants::SHADOW_THICKNESS,
+    )
+}
+
+/* #region CPU REFERENCE */
+// This follows `shadow()` in lighting.wgsl line for line, so known scenes
+// can be checked without a gpu.
+
+#[cfg(test)]
+pub mod cpu {
+    use crate::constants;
+    use crate::game::screen::gbuffer::cpu::depth_buf_to_z;
+
+    pub fn shadow(
+        depths: &[u32],
+        size: (u32, u32),
+        p: [f32; 3],
+        l_pos: [f32; 3],
+    ) -> f32 {
+        let ray = [l_pos[0] - p[0], l_pos[1] - p[1], l_pos[2] - p[2]];
+        for i in 1..constants::SHADOW_STEPS {
+            let t = i as f32 / constants::SHADOW_STEPS as f32;
+            let s = [p[0] + ray[0] * t, p[1] + ray[1] * t, p[2] + ray[2] * t];
+            if s[0] < 0.0 || s[1] < 0.0
+            || s[0] >= size.0 as f32 || s[1] >= size.1 as f32 {
+                break;
+            }
+
+            let stored = depths[(s[0] as u32 + s[1] as u32 * size.0) as usize];
+            if stored == 0 {
+                continue;
+            }
+
+            let diff = depth_buf_to_z(stored) - s[2];
+            if diff > constants::SHADOW_BIAS
+            && diff < constants::SHADOW_THICKNESS {
+                return 0.0;
+            }
+        }
+        1.0
+    }
+
+    /// `true` for every covered pixel that `light` can't see.
+    pub fn shadow_mask(depths: &[u32], size: (u32, u32), light: [f32; 3])
+        -> Vec<bool>
+    {
+        let mut out = vec![false; depths.len()];
+        for y in 0..size.1 {
+            for x in 0..size.0 {
+                let idx = (x + y * size.0) as usize;
+                if depths[idx] == 0 {
+                    continue;
+                }
+                let p = [x as f32, y as f32, depth_buf_to_z(depths[idx])];
+                out[idx] = shadow(depths, size, p, light) == 0.0;
+            }
+        }
+        out
+    }
+}
+/* #endregion */
+
+#[cfg(test)]
+mod tests {
+    use super::cpu::*;
+    use crate::constants;
+    use crate::game::screen::gbuffer::cpu::z_to_depth_buf;
+
+    const SIZE: (u32, u32) = (64, 64);
+
+    fn scene(height: impl Fn(u32, u32) -> f32) -> Vec<u32> {
+        let mut out = Vec::new();
+        for y in 0..SIZE.1 {
+            for x in 0..SIZE.0 {
+                out.push(z_to_depth_buf(height(x, y)));
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn wall_casts_shadow_away_from_light() {
+        let depths = scene(|x, _|
+            if (30..34).contains(&x) { 100.0 } else { 0.0 }
+        );
+        let mask = shadow_mask(&depths, SIZE, [10.0, 32.0, 150.0]);
+
+        for y in 0..SIZE.1 {
+            for x in 0..SIZE.0 {
+                let shadowed = mask[(x + y * SIZE.0) as usize];
+                assert_eq!(shadowed, x >= 34, "pixel ({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn sloped_floor_does_not_shadow_itself() {
+        let depths = scene(|_, y| y as f32 * 2f32.sqrt());
+        let mask = shadow_mask(&depths, SIZE, [32.0, 32.0, 400.0]);
+        assert!(mask.iter().all(|s| !s));
+    }
+
+    #[test]
+    fn occluders_thicker_than_limit_are_ignored() {
+        let depths = scene(|x, _| if (30..34).contains(&x) {
+            constants::SHADOW_THICKNESS + 150.0
+        } else {
+            0.0
+        });
+        let mask = shadow_mask(&depths, SIZE, [10.0, 32.0, 150.0]);
+        assert!(!mask[(50 + 32 * SIZE.0) as usize]);
+    }
+
+    #[test]
+    fn empty_pixels_never_occlude() {
+        // An empty pixel's 0 reads back as the far plane, so a ray running
+        // below it would be blocked if the gap were taken for geometry.
+        let p = [40.0, 32.0, constants::FAR_PLANE + 1.0];
+        let light = [10.0, 32.0, constants::FAR_PLANE - 100.0];
+        let mut depths = vec![0; (SIZE.0 * SIZE.1) as usize];
+        depths[(40 + 32 * SIZE.0) as usize] = z_to_depth_buf(p[2]);
+        assert_eq!(shadow(&depths, SIZE, p, light), 1.0);
+
+        // a wall at the nearest depth above empty does block it
+        for x in 20..30 {
+            depths[(x + 32 * SIZE.0) as usize] = 1;
+        }
+        assert_eq!(shadow(&depths, SIZE, p, light), 0.0);
+    }
+}
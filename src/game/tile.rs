@@ -1,53 +1,51 @@
 pub mod permutation;
-mod mapdata;
+pub mod mapdata;
 
 use nalgebra::{ Vector4, Matrix4 };
 
 use self::permutation::*;
 
-const SQ3: f32 = 1.73205080757;
+const SQ3: f64 = 1.732_050_807_568_877_2;
+
+/// The corners of every tile's square, in its own coordinates, going
+/// around it. Its sides are the geodesics between them.
+pub const CORNERS: [[f64; 4]; 4] = [
+    [1.0, 1.0, 0.0, SQ3],
+    [1.0, -1., 0.0, SQ3],
+    [-1., -1., 0.0, SQ3],
+    [-1., 1.0, 0.0, SQ3],
+];
 
 pub struct Tile {
     code: GroupElt,
-    vertices: &'static [u8],
-    indices: &'static [u8],
-
-    vbuf: Option<wgpu::Buffer>,
-    ibuf: Option<wgpu::Buffer>,
-    pos: Option<wgpu::Buffer>,
-    bind_group: Option<wgpu::BindGroup>,
+    // which map data it has, and how detailed a mesh it's drawn with
+    kind: usize,
+    lod: usize,
+    // where the renderer keeps this tile's transform, see `Geometry`
+    slot: Option<u32>,
 }
 
 impl Tile {
     pub fn new(
         mut code: GroupElt,
     ) -> Tile {
-        let (vertices, indices) = mapdata::get_map_data(code.get_id());
+        let kind = mapdata::get_map_data(code.get_id());
         code.make_repr();
         Self {
             code,
-            vertices,
-            indices,
-
-            vbuf: None,
-            ibuf: None,
-            pos: None,
-            bind_group: None,
+            kind,
+            lod: 0,
+            slot: None,
         }
     }
 
     pub fn update_neighbors(
         &mut self,
-        position: &[f32; 4],
+        position: &[f64; 4],
         existing_codes: &[u32],
     ) -> (Vec<Tile>, Vec<u32>) {
         let pos = Vector4::from_column_slice(position);
-        let mut corners = [
-            Vector4::new(1.0, 1.0, 0.0, SQ3),
-            Vector4::new(1.0, -1., 0.0, SQ3),
-            Vector4::new(-1., -1., 0.0, SQ3),
-            Vector4::new(-1., 1.0, 0.0, SQ3),
-        ];
+        let mut corners = CORNERS.map(Vector4::from);
         let mut distances = [0.0; 4];
 
         let mut out = (Vec::<Tile>::new(), Vec::<u32>::new());
@@ -73,70 +71,89 @@ impl Tile {
         out
     }
 
-    pub fn move_to(&mut self, code: GroupElt) -> Vec<GroupElt> {
-        self.code = code;
-        let mut out = Vec::<GroupElt>::new();
-
-        for _ in 0..4 {
-            let mut new_code = self.code.multiply(&TRANSLATION);
-            new_code.make_repr();
-
-            out.push(new_code);
-            self.code.right_multiply_in_place(&ROTATION);
-        }
-
-        out
+    /// Moves the tile by the isometry `m`, the way re-centering does.
+    pub fn move_by(&mut self, m: &Matrix4<f64>) {
+        self.code.move_by(m);
     }
 
-    pub fn distance_from(&self, position: &[f32; 4],) -> f32 {
+    pub fn distance_from(&self, position: &[f64; 4],) -> f64 {
         let pos = Vector4::from_column_slice(position);
         let center = self.get_mat() * Vector4::new(0.0, 0.0, 0.0, 1.0);
-        let distance = (center.w * pos.w
-            - center.xy().dot(&pos.xy())).acosh();
-        
-        distance
+        // rounding can take it just under 1 on top of the tile's middle
+        (center.w * pos.w - center.xy().dot(&pos.xy())).max(1.0).acosh()
     }
 
-    pub fn get_vertices(&self) -> &[u8] { self.vertices }
-    pub fn get_indices(&self) -> &[u8] { self.indices }
-    pub fn get_mat(&self) -> Matrix4<f32>
-        { self.code.get_matrix() }
-
-    pub fn centered_code(&self) -> GroupElt
-        { self.code.permute_only()}
-    pub fn move_against(
-        &self,
-        pos: &[f32; 4],
-        dir: &[f32; 3]
-    ) -> ([f32; 4], [f32; 3]) {
-        let pos = Vector4::from_column_slice(pos);
-        let dir = Vector4::from_column_slice(
-            &[dir[0], dir[1], 0.0, dir[2]]
-        );
-
-        let inv = self.code.get_matrix().try_inverse().unwrap();
-        let new_pos = inv * pos;
-        let new_dir = inv * dir;
-
-        ([new_pos[0], new_pos[1], new_pos[2], new_pos[3]],
-            [new_dir[0], new_dir[1], new_dir[3]])
+    /// index into `mapdata::meshes()`
+    pub fn get_mesh(&self) -> usize { mapdata::mesh_id(self.kind, self.lod) }
+    pub fn get_lod(&self) -> usize { self.lod }
+    /// Switches meshes, so the tile can't be holding a slot.
+    pub fn set_lod(&mut self, lod: usize) {
+        debug_assert!(self.slot.is_none());
+        self.lod = lod;
     }
+    pub fn get_slot(&self) -> Option<u32> { self.slot }
+    pub fn set_slot(&mut self, slot: Option<u32>) { self.slot = slot; }
+    pub fn get_mat(&self) -> Matrix4<f64>
+        { self.code.get_matrix() }
 
     pub fn get_code(&mut self) -> u32 { self.code.get_id() }
     // pub fn get_code_nonmut(&self) -> u32 {self.code.id.unwrap()}
+}
+
+impl Drop for Tile {
+    /// A slot still held here would be lost to its pool for good.
+    fn drop(&mut self) {
+        if !std::thread::panicking() {
+            debug_assert!(self.slot.is_none(), "tile dropped holding a slot");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::isometry;
 
-    pub fn get_vbuf(&self) -> Option<&wgpu::Buffer> { self.vbuf.as_ref() }
-    // pub fn get_ibuf(&self) -> Option<&wgpu::Buffer> { self.ibuf.as_ref() }
-    pub fn get_pos(&self) -> Option<&wgpu::Buffer> { self.pos.as_ref() }
+    fn assert_close(a: &Matrix4<f64>, b: &Matrix4<f64>) {
+        assert!((a - b).abs().max() < 1.0e-9, "{} {}", a, b);
+    }
 
-    pub fn get_size(&self) -> u32 { (self.indices.len() / 4) as u32 }
+    #[test]
+    fn moved_tiles_keep_their_neighbours() {
+        let next = IDENTITY.neighbors()[2];
+        let back = isometry::inverse(&next.get_matrix());
+        let mut moved = Tile::new(next);
+        moved.move_by(&back);
+        assert_close(&moved.get_mat(), &Matrix4::identity());
+        // what's next to it moves right along with it
+        for (a, mut b) in next.neighbors().into_iter()
+            .zip(moved.code.neighbors())
+        {
+            let mut a = Tile::new(a);
+            a.move_by(&back);
+            assert_eq!(a.get_code(), b.get_id());
+            assert_close(&a.get_mat(), &b.get_matrix());
+        }
+    }
 
-    pub fn set_vbuf(&mut self, buf: wgpu::Buffer) { self.vbuf = Some(buf); }
-    pub fn set_ibuf(&mut self, buf: wgpu::Buffer) { self.ibuf = Some(buf); }
-    pub fn set_pos(&mut self, buf: wgpu::Buffer) { self.pos = Some(buf); }
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "holding a slot")]
+    fn tiles_hand_their_slot_back_before_dropping() {
+        let mut tile = Tile::new(IDENTITY);
+        tile.set_slot(Some(0));
+    }
 
-    pub fn get_bind_group(&self) -> Option<&wgpu::BindGroup>
-        { self.bind_group.as_ref() }
-    pub fn set_bind_group(&mut self, bg: wgpu::BindGroup)
-        { self.bind_group = Some(bg); }
+    #[test]
+    fn moving_back_and_forth_goes_nowhere() {
+        let mut start = IDENTITY.neighbors()[1].neighbors()[3];
+        let mut tile = Tile::new(start);
+        for code in IDENTITY.neighbors().iter().cycle().take(10_000) {
+            let m = code.get_matrix();
+            tile.move_by(&m);
+            tile.move_by(&isometry::inverse(&m));
+        }
+        assert_eq!(tile.get_code(), start.get_id());
+        assert_close(&tile.get_mat(), &start.get_matrix());
+    }
 }
@@ -1,6 +1,272 @@
-pub const TILE_VERTICES: &'static [u8] = include_bytes!("../meshes/vertex_data");
-pub const TILE_INDICES: &'static [u8] = include_bytes!("../meshes/index_data");
+use std::sync::OnceLock;
 
-pub fn get_map_data(id: u32) -> (&'static [u8], &'static [u8]) {
-    (TILE_VERTICES, TILE_INDICES)
+use crate::constants;
+use crate::game::material;
+
+pub const TILE_VERTICES: &[u8] = include_bytes!("../meshes/vertex_data");
+
+/// The material of each cell of the baked grid, a line of them for each
+/// step along the tile's first side, see `material_of`.
+const TILE_MATERIALS: &str = include_str!("../meshes/material_data");
+
+/// Vertices along each side of the baked tile.
+const TILE_GRID: usize = 33;
+
+/// Meshes each kind of tile has, from full detail down. Each keeps every
+/// other vertex of the one before it along both sides, except along the
+/// tile's edges, where every level keeps them all so neighbours drawn at
+/// different levels still meet.
+pub const LOD_LEVELS: usize = constants::LOD_DISTANCES.len() + 1;
+
+/// Geometry any number of tiles can be drawn with. Each one is uploaded to
+/// the gpu once, and tiles only add a transform.
+pub struct Mesh {
+    pub vertices: Vec<u8>,
+    pub indices: Vec<u8>,
+    /// one material id per triangle
+    pub materials: Vec<u32>,
+}
+
+impl Mesh {
+    pub fn index_count(&self) -> u32 { (self.indices.len() / 4) as u32 }
+}
+
+pub fn meshes() -> &'static [Mesh] {
+    static MESHES: OnceLock<Vec<Mesh>> = OnceLock::new();
+    MESHES.get_or_init(|| (0..LOD_LEVELS)
+        .map(|lod| decimate(TILE_VERTICES, 1 << lod))
+        .collect()
+    )
+}
+
+/// Which kind of tile the tile with this id is.
+pub fn get_map_data(_id: u32) -> usize {
+    0
+}
+
+/// Which of `meshes()` a tile of `kind` is drawn with at `lod`.
+pub fn mesh_id(kind: usize, lod: usize) -> usize {
+    kind * LOD_LEVELS + lod
+}
+
+/// The level of detail for a tile whose center is `distance` from the
+/// camera, as `Tile::distance_from` measures it, when it's drawn at
+/// `current` now. It only switches once it's `LOD_HYSTERESIS` past one of
+/// `LOD_DISTANCES`, so a tile sitting on one doesn't flip every update.
+pub fn lod_for_distance(distance: f64, current: usize) -> usize {
+    constants::LOD_DISTANCES.iter().enumerate().filter(|&(i, &d)|
+        if i < current {
+            distance > d - constants::LOD_HYSTERESIS
+        } else {
+            distance > d + constants::LOD_HYSTERESIS
+        }
+    ).count()
+}
+
+/// Whether the vertex at `x`, `y` on the baked grid is on the tile's edge.
+fn on_edge(x: usize, y: usize) -> bool {
+    x == 0 || y == 0 || x == TILE_GRID - 1 || y == TILE_GRID - 1
+}
+
+/// The tile's grid with only every `step`th vertex along each side, and
+/// every vertex on its edges.
+fn decimate(vertices: &[u8], step: usize) -> Mesh {
+    // vertices are 8 floats, and go along the tile's second side first
+    let kept = |x: usize, y: usize|
+        (x.is_multiple_of(step) && y.is_multiple_of(step)) || on_edge(x, y);
+    let mut index = vec![u32::MAX; TILE_GRID * TILE_GRID];
+    let mut grid = Vec::new();
+    let mut out = Vec::new();
+    for i in 0..TILE_GRID * TILE_GRID {
+        let (x, y) = (i / TILE_GRID, i % TILE_GRID);
+        if kept(x, y) {
+            index[i] = grid.len() as u32;
+            grid.push([x, y]);
+            out.extend_from_slice(&vertices[32 * i..32 * i + 32]);
+        }
+    }
+    let indices = grid_indices(step, |x, y| index[x * TILE_GRID + y]);
+    // each triangle is made of the cell its middle is in
+    let materials = indices.chunks_exact(3).map(|tri| {
+        let [x, y] = [0, 1].map(|j|
+            tri.iter().map(|&v| grid[v as usize][j]).sum::<usize>() / 3);
+        material_of(x, y)
+    }).collect();
+    Mesh {
+        vertices: out,
+        indices: bytemuck::cast_slice(&indices).to_vec(),
+        materials,
+    }
+}
+
+/// Triangles for each `step` by `step` cell of the baked grid, whose
+/// vertices `at` gives the index of. Cells along the tile's edges also have
+/// the edge's vertices in between, and are fanned out from a corner inside
+/// the tile. The rest are split in two, with the diagonals alternating like
+/// the baked tile's.
+fn grid_indices(step: usize, at: impl Fn(usize, usize) -> u32) -> Vec<u32> {
+    let cells = (TILE_GRID - 1) / step;
+    let mut out = Vec::new();
+    for cy in 0..cells {
+        for cx in 0..cells {
+            let (x, y) = (cx * step, cy * step);
+            let (x1, y1) = (x + step, y + step);
+            let edge = |v: usize| v == 0 || v == TILE_GRID - 1;
+            if step > 1 && (edge(x) || edge(y) || edge(x1) || edge(y1)) {
+                // counter-clockwise around the cell, starting from the
+                // corner inside the tile, with every vertex in between
+                // on sides along the tile's edge
+                let corners = [(x, y), (x1, y), (x1, y1), (x, y1)];
+                let inside = corners.iter()
+                    .position(|&(x, y)| !on_edge(x, y)).unwrap();
+                let mut around = Vec::new();
+                for k in inside..inside + 4 {
+                    let (a, b) = (corners[k % 4], corners[(k + 1) % 4]);
+                    let along = (a.0 == b.0 && edge(a.0))
+                        || (a.1 == b.1 && edge(a.1));
+                    let n = if along { step } else { 1 };
+                    around.extend((0..n).map(|j| (
+                        (a.0 * (n - j) + b.0 * j) / n,
+                        (a.1 * (n - j) + b.1 * j) / n,
+                    )));
+                }
+                for k in 1..around.len() - 1 {
+                    out.extend([around[0], around[k], around[k + 1]]
+                        .map(|(x, y)| at(x, y)));
+                }
+            } else if (cx + cy) % 2 == 0 {
+                out.extend([at(x, y), at(x1, y), at(x, y1)]);
+                out.extend([at(x, y1), at(x1, y), at(x1, y1)]);
+            } else {
+                out.extend([at(x, y), at(x1, y1), at(x, y1)]);
+                out.extend([at(x, y), at(x1, y), at(x1, y1)]);
+            }
+        }
+    }
+    out
+}
+
+/// The material of the cell of the baked grid at `x`, `y`, written in
+/// `TILE_MATERIALS` as one of
+/// - `.` ground
+/// - `~` water
+/// - `*` crystal
+/// - `o` the marker
+fn material_of(x: usize, y: usize) -> u32 {
+    let line = TILE_MATERIALS.lines().nth(x).unwrap();
+    match line.as_bytes()[y] {
+        b'.' => material::GROUND,
+        b'~' => material::WATER,
+        b'*' => material::CRYSTAL,
+        b'o' => material::MARKER,
+        other => panic!("no material {:?} in material_data", other as char),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_detail_matches_the_baked_tile() {
+        let baked = include_bytes!("../meshes/index_data");
+        let mesh = &meshes()[mesh_id(0, 0)];
+        assert_eq!(mesh.vertices, TILE_VERTICES);
+        assert_eq!(mesh.indices, baked);
+    }
+
+    /// The position of each of `mesh`'s vertices.
+    fn positions(mesh: &Mesh) -> Vec<[f32; 4]> {
+        let floats: &[f32] = bytemuck::cast_slice(&mesh.vertices);
+        floats.chunks_exact(8).map(|v| [v[0], v[1], v[2], v[3]]).collect()
+    }
+
+    /// Every side of a triangle in `mesh` that only one triangle has, as
+    /// its ends' positions, in order.
+    fn outline(mesh: &Mesh) -> Vec<[[u32; 4]; 2]> {
+        let indices: &[u32] = bytemuck::cast_slice(&mesh.indices);
+        let sides: Vec<_> = indices.chunks_exact(3).flat_map(|t|
+            [(t[0], t[1]), (t[1], t[2]), (t[2], t[0])]).collect();
+        let at = positions(mesh);
+        let mut out: Vec<_> = sides.iter()
+            .filter(|&&(a, b)| !sides.contains(&(b, a)))
+            .map(|&(a, b)| [a, b].map(|i| at[i as usize].map(f32::to_bits)))
+            .collect();
+        out.sort();
+        out
+    }
+
+    #[test]
+    fn every_cell_has_a_material() {
+        assert_eq!(TILE_MATERIALS.lines().count(), TILE_GRID - 1);
+        for (x, line) in TILE_MATERIALS.lines().enumerate() {
+            assert_eq!(line.len(), TILE_GRID - 1, "line {}", x);
+            for y in 0..TILE_GRID - 1 {
+                material_of(x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn levels_get_coarser() {
+        for lod in 1..LOD_LEVELS {
+            let finer = &meshes()[mesh_id(0, lod - 1)];
+            let coarser = &meshes()[mesh_id(0, lod)];
+            assert!(coarser.index_count() * 2 < finer.index_count());
+            assert_eq!(coarser.materials.len() * 3,
+                coarser.index_count() as usize);
+        }
+    }
+
+    #[test]
+    fn every_level_has_the_same_edges() {
+        // so neighbours at any two levels share every edge vertex, with
+        // nothing in the middle of the tile left uncovered or doubled up
+        let full = outline(&meshes()[mesh_id(0, 0)]);
+        assert_eq!(full.len(), 4 * (TILE_GRID - 1));
+        for lod in 1..LOD_LEVELS {
+            assert_eq!(outline(&meshes()[mesh_id(0, lod)]), full);
+        }
+    }
+
+    #[test]
+    fn triangles_keep_their_winding() {
+        // every triangle faces the same way as the baked tile's do
+        for mesh in meshes() {
+            let at = positions(mesh);
+            let indices: &[u32] = bytemuck::cast_slice(&mesh.indices);
+            for t in indices.chunks_exact(3) {
+                let [a, b, c] = [0, 1, 2].map(|k| at[t[k] as usize]);
+                let cross = (b[0] - a[0]) * (c[1] - a[1])
+                    - (b[1] - a[1]) * (c[0] - a[0]);
+                assert!(cross > 0.0, "{:?}", t);
+            }
+        }
+    }
+
+    #[test]
+    fn detail_drops_with_distance() {
+        assert_eq!(lod_for_distance(0.0, 0), 0);
+        assert_eq!(lod_for_distance(100.0, 0), LOD_LEVELS - 1);
+        let mut lod = 0;
+        for step in 0..100 {
+            let next = lod_for_distance(step as f64 * 0.05, lod);
+            assert!(next >= lod);
+            lod = next;
+        }
+    }
+
+    #[test]
+    fn switching_back_takes_a_step_back() {
+        let d = constants::LOD_DISTANCES[0];
+        let h = constants::LOD_HYSTERESIS;
+        // sitting on the distance, it stays whichever it was
+        assert_eq!(lod_for_distance(d, 0), 0);
+        assert_eq!(lod_for_distance(d, 1), 1);
+        assert_eq!(lod_for_distance(d + 1.5 * h, 0), 1);
+        assert_eq!(lod_for_distance(d - 1.5 * h, 1), 0);
+        // jumping straight across more than one level still works
+        assert_eq!(lod_for_distance(100.0, 0), LOD_LEVELS - 1);
+        assert_eq!(lod_for_distance(0.0, LOD_LEVELS - 1), 0);
+    }
 }
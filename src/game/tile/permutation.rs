@@ -1,4 +1,5 @@
 use nalgebra::Matrix4;
+use crate::game::isometry;
 use super::SQ3;
 
 #[derive(Clone, Copy)]
@@ -9,7 +10,7 @@ pub struct Permutation {
 #[derive(Clone, Copy)]
 pub struct GroupElt {
     perm: Permutation,
-    matrix: Matrix4<f32>,
+    matrix: Matrix4<f64>,
     pub id: Option<u32>,
 }
 
@@ -62,11 +63,7 @@ impl Permutation {
     }
 
     fn multiply(&self, other: &Permutation) -> Permutation {
-        let mut out = [0usize; 9];
-        for i in 0..9 {
-            out[i] = self.func[other.func[i]];
-        }
-        return Permutation {func: out}
+        Permutation { func: other.func.map(|i| self.func[i]) }
     }
 
     // fn left_multiply_in_place(&mut self, other: &Permutation) {
@@ -78,14 +75,10 @@ impl Permutation {
     // }
 
     fn right_multiply_in_place(&mut self, other: &Permutation) {
-        let mut out = [0usize; 9];
-        for i in 0..9 {
-            out[i] = self.func[other.func[i]];
-        }
-        self.func = out;
+        self.func = other.func.map(|i| self.func[i]);
     }
 
-    fn to_int(&self) -> u32 {
+    fn to_int(self) -> u32 {
         let mut out = 0u32;
         let mut factor = 1u32;
         for i in 0..9 {
@@ -101,6 +94,23 @@ impl Permutation {
         out
     }
 
+    /// The permutation `to_int` gives `n` for.
+    fn from_int(mut n: u32) -> Permutation {
+        // how many bigger values come before each one, which picks it out
+        // of those still left, last one first
+        let mut more_than = [0; 9];
+        for (i, digit) in more_than.iter_mut().enumerate() {
+            *digit = n as usize % (i + 1);
+            n /= (i + 1) as u32;
+        }
+        let mut left: Vec<usize> = (0..9).collect();
+        let mut func = [0; 9];
+        for i in (0..9).rev() {
+            func[i] = left.remove(i - more_than[i]);
+        }
+        Permutation { func }
+    }
+
     fn repr(&self) -> u32 {
         let mut out = self.to_int();
         let mut current = self.multiply(&Permutation::identity());
@@ -127,7 +137,7 @@ impl GroupElt {
 
     pub fn right_multiply_in_place(&mut self, other: &GroupElt) {
         self.perm.right_multiply_in_place(&other.perm);
-        self.matrix = self.matrix * other.matrix;
+        self.matrix *= other.matrix;
 
         if other.id != Some(0) {
             self.id = None;
@@ -151,7 +161,29 @@ impl GroupElt {
         }
     }
 
-    pub fn get_matrix(&self) -> Matrix4<f32> { self.matrix }
+    /// The tile with id `id`, turned the way `make_repr` leaves it, at the
+    /// origin.
+    pub fn from_id(id: u32) -> GroupElt {
+        GroupElt {
+            perm: Permutation::from_int(id),
+            matrix: Matrix4::identity(),
+            id: Some(id),
+        }
+    }
+
+    /// The tiles across each of this one's sides, each turned the way
+    /// `make_repr` leaves it.
+    pub fn neighbors(&self) -> [GroupElt; 4] {
+        let mut code = *self;
+        [(); 4].map(|_| {
+            let mut next = code.multiply(&TRANSLATION);
+            next.make_repr();
+            code.right_multiply_in_place(&ROTATION);
+            next
+        })
+    }
+
+    pub fn get_matrix(&self) -> Matrix4<f64> { self.matrix }
     pub fn get_id(&mut self) -> u32 {
         match self.id {
             None => {self.id = Some(self.perm.repr()); self.id.unwrap()},
@@ -159,7 +191,13 @@ impl GroupElt {
         }
     }
 
-    pub fn permute_only(&self) -> GroupElt {
-        GroupElt { perm: self.perm, matrix: Matrix4::identity(), id: self.id }
+    /// Moves the tile by the isometry `m`, keeping which tile it is.
+    pub fn move_by(&mut self, m: &Matrix4<f64>) {
+        let moved = m * self.matrix;
+        // evened out, so moving it over and over can't pile up rounding
+        self.matrix = isometry::frame(
+            [0, 1, 2, 3].map(|i| moved[(i, 3)]),
+            [0, 1, 3].map(|i| moved[(i, 1)]),
+        );
     }
 }
\ No newline at end of file
@@ -4,14 +4,21 @@ use game_loop::game_loop;
 use game_loop::winit::event_loop::EventLoop;
 use game_loop::winit::window::WindowBuilder;
 
+mod config;
 mod game;
 mod constants;
 
-async fn run() {
+async fn run(config: config::Config) {
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new().build(&event_loop).unwrap();
 
-    let game = game::Game::new(&window).await;
+    let game = match game::Game::new(&window, &config).await {
+        Ok(game) => game,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
 
     game_loop(event_loop, window, game, 30, 0.1, |g| {
         g.game.update();
@@ -30,6 +37,58 @@ async fn run() {
     });
 }
 
+/// A headless game for `config`, or out with the reason there isn't one.
+fn headless(config: &config::Config) -> game::Game {
+    match pollster::block_on(game::Game::headless(config)) {
+        Ok(game) => game,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
 fn main() {
-    pollster::block_on(run());
+    env_logger::init();
+    let config = match config::Config::from_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    if let Some(dir) = &config.export {
+        let mut game = headless(&config);
+        if let Err(e) = game.play() {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        if let Err(e) = std::fs::create_dir_all(dir) {
+            eprintln!("couldn't make {}: {}", dir.display(), e);
+            std::process::exit(1);
+        }
+        // one frame an update, so 30 a second
+        for frame in 0.. {
+            game.update();
+            if !game.playing() {
+                break;
+            }
+            let path = dir.join(format!("frame{:05}.png", frame));
+            if let Err(e) = game.screenshot(&path) {
+                eprintln!("couldn't save {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if let Some(path) = &config.screenshot {
+        let mut game = headless(&config);
+        game.settle();
+        if let Err(e) = game.screenshot(path) {
+            eprintln!("couldn't save {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    pollster::block_on(run(config));
 }
\ No newline at end of file